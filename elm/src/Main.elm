@@ -43,6 +43,7 @@ port requestValidmoves : (String, Int) -> Cmd msg
 port requestMinimax : String -> Cmd msg
 port gamestateReceiver : (String -> msg) -> Sub msg
 port validmovesReceiver : (String -> msg) -> Sub msg
+port errorReceiver : (String -> msg) -> Sub msg
 
 
 
@@ -95,6 +96,7 @@ type Msg
   | Click Int
   | RecvGameState (Result D.Error GameState)
   | RecvValidmoves (Result D.Error (List PotentialMove))
+  | RecvError String
   | Restart
   | PlayHuman
   | PlayAI
@@ -180,6 +182,11 @@ update msg model =
       , Cmd.none
       )
 
+    RecvError errMsg ->
+      ( { model | error = Just errMsg }
+      , Cmd.none
+      )
+
     GotNewWindowSize width height ->
       ( { model | windowSize = (width, height)
         }
@@ -211,6 +218,7 @@ subscriptions _ =
     , Browser.Events.onResize GotNewWindowSize
     , gamestateReceiver (\json -> D.decodeString gameStateDecoder json |> RecvGameState)
     , validmovesReceiver (\json -> D.decodeString potentialMovesDecoder json |> RecvValidmoves)
+    , errorReceiver RecvError
     ]
 
 
@@ -237,7 +245,9 @@ view model =
                    , centerY
                    ]
               [ buttons model
+              , pocketView (boardSize model) Black model.gamestate
               , boardView (boardSize model) model.gamestate.board highlightedFields Click
+              , pocketView (boardSize model) White model.gamestate
               , winMessage model
               , errorMessage model
               --, text <| String.fromInt (Tuple.first model.windowSize) ++ "x" ++ String.fromInt (Tuple.second model.windowSize)
@@ -317,7 +327,13 @@ winMessage model =
           Checkmate -> text <| other ++ " wins!"
           Stalemate -> text <| "Draw! " ++ mover ++ " can not move (Stalemate)."
           ThreefoldRepetition -> text <| "Draw (threefold board repetition)."
+          FivefoldRepetition -> text <| "Draw (fivefold board repetition)."
           FiftyMoveDraw -> text <| "Draw (fifty move rule)."
+          InsufficientMaterial -> text <| "Draw (insufficient material)."
+          KingOfTheHill -> text <| other ++ " wins (king of the hill)!"
+          ThreeChecks -> text <| other ++ " wins (three checks)!"
+          KingExploded -> text <| other ++ " wins (king exploded)!"
+          OutOfMoves -> text <| mover ++ " wins (no pieces or moves left)!"
 
 errorMessage : Model -> Element Msg
 errorMessage model =
@@ -371,8 +387,14 @@ type FinishedState
   = Ongoing -- the player whose turn it is is guaranteed to have at least one legal move!
   | Checkmate
   | Stalemate
-  | ThreefoldRepetition -- not implemented yet as of 2020-01-04
+  | ThreefoldRepetition
+  | FivefoldRepetition
   | FiftyMoveDraw
+  | InsufficientMaterial
+  | KingOfTheHill
+  | ThreeChecks
+  | KingExploded
+  | OutOfMoves
 
 type alias BoardState = 
   { fields: List (Maybe OccupiedField)
@@ -395,6 +417,16 @@ type alias GameState =
   , fifty_move_rule_last_event : Int
   , board : BoardState
   , finished : FinishedState
+  , rest : List (String, E.Value) -- e.g. the variant, only needed in Rust
+  }
+
+-- Crazyhouse: the captured pieces a player may drop
+type alias Pocket =
+  { queens : Int
+  , rooks : Int
+  , bishops : Int
+  , knights : Int
+  , pawns : Int
   }
 
 type alias PotentialMove =
@@ -431,6 +463,7 @@ newGame =
   , fifty_move_rule_last_event = 0
   , board = newBoard
   , finished = Ongoing
+  , rest = []
   }
 
 turn : GameState -> Player
@@ -451,11 +484,41 @@ potentialMoveDecoder =
 
 gameStateDecoder : D.Decoder GameState
 gameStateDecoder =
-  D.map4 GameState
+  D.map5 GameState
     (D.field "ply" D.int)
     (D.field "fifty_move_rule_last_event" D.int)
     (D.field "board" boardStateDecoder)
     (D.field "finished" finishedStateDecoder)
+    restDecoder
+
+-- The fields elm does not know about are sent back to Rust unchanged.
+knownGameStateFields : List String
+knownGameStateFields =
+  [ "ply", "fifty_move_rule_last_event", "board", "finished" ]
+
+restDecoder : D.Decoder (List (String, E.Value))
+restDecoder =
+  D.keyValuePairs D.value
+    |> D.map (List.filter (\(name, _) -> not <| List.member name knownGameStateFields))
+
+-- The pockets are only displayed, so they stay in the rest of the GameState.
+-- They are ordered like the players in Rust: Black first.
+pockets : GameState -> List Pocket
+pockets gs =
+  gs.rest
+    |> List.filter (\(name, _) -> name == "pockets")
+    |> List.head
+    |> Maybe.andThen (\(_, value) -> D.decodeValue (D.list pocketDecoder) value |> Result.toMaybe)
+    |> Maybe.withDefault []
+
+pocketDecoder : D.Decoder Pocket
+pocketDecoder =
+  D.map5 Pocket
+    (D.field "queens" D.int)
+    (D.field "rooks" D.int)
+    (D.field "bishops" D.int)
+    (D.field "knights" D.int)
+    (D.field "pawns" D.int)
 
 boardStateDecoder : D.Decoder BoardState
 boardStateDecoder =
@@ -516,19 +579,25 @@ finishedStateDecoder =
         "Checkmate" -> s Checkmate
         "Stalemate" -> s Stalemate
         "ThreefoldRepetition" -> s ThreefoldRepetition
+        "FivefoldRepetition" -> s FivefoldRepetition
         "FiftyMoveDraw" -> s FiftyMoveDraw
+        "InsufficientMaterial" -> s InsufficientMaterial
+        "KingOfTheHill" -> s KingOfTheHill
+        "ThreeChecks" -> s ThreeChecks
+        "KingExploded" -> s KingExploded
+        "OutOfMoves" -> s OutOfMoves
         _ -> D.fail <| finString ++ " is not a valid FinishedState."
       )
 
 
 gameStateEncoder : GameState -> E.Value
 gameStateEncoder gs =
-  E.object
+  E.object <|
     [ ("ply", E.int gs.ply)
     , ("fifty_move_rule_last_event", E.int gs.fifty_move_rule_last_event)
     , ("board", boardStateEncoder gs.board)
     , ("finished", finishedStateEncoder gs.finished)
-    ]
+    ] ++ gs.rest
 
 boardStateEncoder : BoardState -> E.Value
 boardStateEncoder bs =
@@ -577,8 +646,8 @@ enPassantFieldEncoder : EnPassantFieldInfo -> E.Value
 enPassantFieldEncoder epf =
   E.object
     [ ("ply", E.int epf.ply)
-    , ("skipped", E.int epf.ply)
-    , ("target", E.int epf.ply)
+    , ("skipped", E.int epf.skipped)
+    , ("target", E.int epf.target)
     ]
 
 finishedStateEncoder : FinishedState -> E.Value
@@ -589,11 +658,73 @@ finishedStateEncoder fin =
         Checkmate -> "Checkmate" 
         Stalemate -> "Stalemate" 
         ThreefoldRepetition -> "ThreefoldRepetition" 
+        FivefoldRepetition -> "FivefoldRepetition" 
         FiftyMoveDraw -> "FiftyMoveDraw" 
+        InsufficientMaterial -> "InsufficientMaterial"
+        KingOfTheHill -> "KingOfTheHill"
+        ThreeChecks -> "ThreeChecks"
+        KingExploded -> "KingExploded"
+        OutOfMoves -> "OutOfMoves"
   )
 
 -- CHESS view
 
+pieceImgSrc : OccupiedField -> String
+pieceImgSrc ptp =
+  case (ptp.piece_type, ptp.player) of
+    (InitKing, Black) -> "black_king.png"
+    (King, Black) -> "black_king.png"
+    (Queen, Black) -> "black_queen.png"
+    (InitRook, Black) -> "black_rook.png"
+    (Rook, Black) -> "black_rook.png"
+    (Bishop, Black) -> "black_bishop.png"
+    (Knight, Black) -> "black_knight.png"
+    (InitPawn, Black) -> "black_pawn.png"
+    (Pawn, Black) -> "black_pawn.png"
+    (InitKing, White) -> "white_king.png"
+    (King, White) -> "white_king.png"
+    (Queen, White) -> "white_queen.png"
+    (InitRook, White) -> "white_rook.png"
+    (Rook, White) -> "white_rook.png"
+    (Bishop, White) -> "white_bishop.png"
+    (Knight, White) -> "white_knight.png"
+    (InitPawn, White) -> "white_pawn.png"
+    (Pawn, White) -> "white_pawn.png"
+
+pocketView : Int -> Player -> GameState -> Element Msg
+pocketView size player gs =
+  let
+      fieldSize = size // 8
+      index = case player of
+        Black -> 0
+        White -> 1
+      pieces = case List.head <| List.drop index <| pockets gs of
+        Nothing -> []
+        Just p -> List.filter (\(_, count) -> count > 0)
+          [ (Queen, p.queens)
+          , (Rook, p.rooks)
+          , (Bishop, p.bishops)
+          , (Knight, p.knights)
+          , (Pawn, p.pawns)
+          ]
+      pieceCount = \(piece, count) ->
+        row [ spacing 2 ]
+          [ image [ width <| px (fieldSize // 2)
+                  , height <| px (fieldSize // 2)
+                  ] { src = "web/piece-images/" ++ pieceImgSrc (OccupiedField piece player)
+                    , description = pieceImgSrc (OccupiedField piece player)
+                    }
+          , text <| String.fromInt count
+          ]
+  in
+    if List.isEmpty pieces
+      then none
+      else
+        row [ width <| px size
+            , height <| px (fieldSize // 2)
+            , spacing 10
+            ] <| List.map pieceCount pieces
+
 boardView : Int -> BoardState -> List Int -> (Int -> Msg) -> Element Msg
 boardView size board highlightedFields msg =
   let
@@ -615,25 +746,6 @@ rowView size rowNum fields highlightedFields msg =
         (False, False) -> rgb255 250 250 250
         (True, True) -> rgb255 100 100 0
         (False, True) -> rgb255 200 200 0
-      pieceImgSrc = \ptp -> case (ptp.piece_type, ptp.player) of
-        (InitKing, Black) -> "black_king.png"
-        (King, Black) -> "black_king.png"
-        (Queen, Black) -> "black_queen.png"
-        (InitRook, Black) -> "black_rook.png"
-        (Rook, Black) -> "black_rook.png"
-        (Bishop, Black) -> "black_bishop.png"
-        (Knight, Black) -> "black_knight.png"
-        (InitPawn, Black) -> "black_pawn.png"
-        (Pawn, Black) -> "black_pawn.png"
-        (InitKing, White) -> "white_king.png"
-        (King, White) -> "white_king.png"
-        (Queen, White) -> "white_queen.png"
-        (InitRook, White) -> "white_rook.png"
-        (Rook, White) -> "white_rook.png"
-        (Bishop, White) -> "white_bishop.png"
-        (Knight, White) -> "white_knight.png"
-        (InitPawn, White) -> "white_pawn.png"
-        (Pawn, White) -> "white_pawn.png"
       pieceImg = \ptp ->
         image [ width fill
               , height fill
@@ -0,0 +1,77 @@
+// Antichess (losing chess): The goal is to lose all pieces. Capturing is
+// compulsory, the king is an ordinary piece which can be captured, there is
+// no check and no castling, and pawns may also promote to a king. A player
+// who has no pieces left or can not move wins.
+
+use crate::game::*;
+use crate::variant::{automatic_draw, Variant};
+
+pub struct Antichess;
+
+const ANTICHESS_PROMOTION_PIECES: [PieceType; 5] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::King,
+];
+
+impl Variant for Antichess {
+    fn name(&self) -> &'static str {
+        "Antichess"
+    }
+
+    // Without castling rights.
+    fn start_position(&self) -> BoardState {
+        BoardState::from_back_rank([
+            PieceType::Rook,
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Queen,
+            PieceType::King,
+            PieceType::Bishop,
+            PieceType::Knight,
+            PieceType::Rook,
+        ])
+    }
+
+    fn royal_king(&self) -> bool {
+        false
+    }
+
+    fn in_check(&self, _board: &BoardState, _player: Player) -> bool {
+        false
+    }
+
+    // Castling moves only exist if a position with castling rights was
+    // loaded, e.g. from a FEN.
+    fn is_legal(&self, _game_state: &GameState, mv: Move) -> bool {
+        !mv.castling
+    }
+
+    fn promotion_pieces(&self) -> &'static [PieceType] {
+        &ANTICHESS_PROMOTION_PIECES
+    }
+
+    fn compulsory_captures(&self) -> bool {
+        true
+    }
+
+    // Kings can be captured, so there is always enough material.
+    fn game_end(&self, game_state: &GameState) -> Option<FinishedState> {
+        automatic_draw(game_state)
+    }
+
+    fn no_legal_moves(&self, _game_state: &GameState) -> FinishedState {
+        FinishedState::OutOfMoves
+    }
+
+    // Losing all pieces is always possible.
+    fn can_win(&self, _game_state: &GameState, _player: Player) -> bool {
+        true
+    }
+
+    fn material_sign(&self) -> i32 {
+        -1
+    }
+}
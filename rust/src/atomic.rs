@@ -0,0 +1,74 @@
+// Atomic chess: Every capture is an explosion which removes the capturing
+// piece and all pieces next to the captured one except for pawns. Blowing up
+// the other king wins, so kings can not capture and a king next to the other
+// king can not be checked.
+
+use crate::bitboard::{bit, KING_ATTACKS};
+use crate::game::*;
+use crate::variant::{automatic_draw, bare_kings, lone_king, Variant};
+
+pub struct Atomic;
+
+fn king_exploded(board: &BoardState, player: Player) -> bool {
+    board.pieces(PieceType::King, player) == 0
+}
+
+fn kings_adjacent(board: &BoardState, player: Player) -> bool {
+    match board.find_king(player) {
+        Ok(king_pos) => KING_ATTACKS[king_pos] & board.pieces(PieceType::King, player.opponent()) != 0,
+        Err(_) => false,
+    }
+}
+
+impl Variant for Atomic {
+    fn name(&self) -> &'static str {
+        "Atomic"
+    }
+
+    fn apply_move(&self, board: &mut BoardState, mv: Move, ply: usize) -> UndoInfo {
+        let mut undo = board.apply_move(mv, ply);
+        if mv.capture {
+            board.explode(mv.to, &mut undo);
+        }
+        undo
+    }
+
+    fn in_check(&self, board: &BoardState, player: Player) -> bool {
+        !king_exploded(board, player)
+            && !king_exploded(board, player.opponent())
+            && !kings_adjacent(board, player)
+            && board.king_in_check(player)
+    }
+
+    fn is_legal(&self, game_state: &GameState, mv: Move) -> bool {
+        let player = game_state.turn();
+        if mv.capture && game_state.board.pieces(PieceType::King, player) & bit(mv.from) != 0 {
+            return false;
+        }
+        let mut board = game_state.board;
+        self.apply_move(&mut board, mv, game_state.ply);
+        if king_exploded(&board, player) {
+            return false;
+        }
+        // Winning is always legal, even if the own king is in check.
+        king_exploded(&board, player.opponent()) || !self.in_check(&board, player)
+    }
+
+    fn game_end(&self, game_state: &GameState) -> Option<FinishedState> {
+        if king_exploded(&game_state.board, game_state.turn()) {
+            return Some(FinishedState::KingExploded);
+        }
+        // Any piece can blow up a king.
+        automatic_draw(game_state).or_else(|| bare_kings(game_state))
+    }
+
+    // Any piece next to the opponent's king can be captured to blow it up.
+    // Against a bare king, the pieces have to checkmate like in chess.
+    fn can_win(&self, game_state: &GameState, player: Player) -> bool {
+        let board = &game_state.board;
+        if lone_king(board, player) {
+            return false;
+        }
+        !lone_king(board, player.opponent()) || !board.insufficient_material()
+    }
+}
@@ -0,0 +1,140 @@
+// Queries about attacked fields, checks and pins, for the front ends and
+// for evaluation ideas which need more than BoardState::king_in_check().
+// Everything is built on BoardState::attackers().
+
+use serde::{Deserialize, Serialize};
+
+use crate::bitboard::{self, bishop_attacks, bit, rook_attacks, serde_fields, Bitboard};
+use crate::game::*;
+
+// A piece which can not leave the ray between its king and the pinner
+// without exposing the king. The ray includes the pinner, so the pinned
+// piece may still move along it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    pub pinned: usize,
+    pub pinner: usize,
+    #[serde(with = "serde_fields")]
+    pub ray: Bitboard,
+}
+
+type SliderAttacks = fn(usize, Bitboard) -> Bitboard;
+
+impl BoardState {
+    // The pieces giving check to the king of player, none if there is no
+    // king.
+    pub fn checkers(&self, player: Player) -> Bitboard {
+        match self.find_king(player) {
+            Ok(king_pos) => self.attackers(king_pos, player.opponent()),
+            Err(_) => 0,
+        }
+    }
+
+    // The absolutely pinned pieces of player: a piece of the opponent would
+    // attack the king if the pinned piece was not there.
+    pub fn pins(&self, player: Player) -> Vec<Pin> {
+        let king_pos = match self.find_king(player) {
+            Ok(king_pos) => king_pos,
+            Err(_) => return vec![],
+        };
+        let opponent = player.opponent();
+        let own = self.player_pieces(player);
+        let queens = self.pieces(PieceType::Queen, opponent);
+        let sliders: [(SliderAttacks, Bitboard); 2] = [
+            (rook_attacks, self.pieces(PieceType::Rook, opponent) | queens),
+            (bishop_attacks, self.pieces(PieceType::Bishop, opponent) | queens),
+        ];
+        let mut pins = Vec::new();
+        for &(slider_attacks, pinners) in sliders.iter() {
+            let attacks = slider_attacks(king_pos, self.occupied());
+            // Look through the own pieces next to the king.
+            let blockers = attacks & own;
+            let xray = slider_attacks(king_pos, self.occupied() ^ blockers) & !attacks;
+            for pinner in bitboard::fields(xray & pinners) {
+                // Both attack the fields between them if nothing else is
+                // in between.
+                let between = slider_attacks(king_pos, bit(pinner))
+                    & slider_attacks(pinner, bit(king_pos));
+                let ray = between | bit(pinner);
+                let pinned = (ray & blockers).trailing_zeros() as usize;
+                pins.push(Pin { pinned, pinner, ray });
+            }
+        }
+        pins.sort_by_key(|pin| pin.pinned);
+        pins
+    }
+
+    // How many pieces of player attack each field.
+    pub fn attack_counts(&self, player: Player) -> [u8; 64] {
+        let mut counts = [0; 64];
+        for (piece, pos) in self.get_pieces_with_pos(player) {
+            for attacked in bitboard::fields(self.attacks(piece, pos, player)) {
+                counts[attacked] += 1;
+            }
+        }
+        counts
+    }
+}
+
+// Everything the front ends draw about attacks, indexed by Player where
+// needed. Bitboards are sent as lists of fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttackInfo {
+    pub checkers: Vec<usize>,
+    pub pins: [Vec<Pin>; 2],
+    pub attack_counts: [Vec<u8>; 2],
+}
+
+impl AttackInfo {
+    // Only the player to move can be in check, the variant decides whether
+    // it is.
+    pub fn new(game_state: &GameState) -> Self {
+        let board = &game_state.board;
+        let players = [Player::Black, Player::White];
+        let checkers = if game_state.in_check() {
+            board.checkers(game_state.turn())
+        } else {
+            0
+        };
+        AttackInfo {
+            checkers: bitboard::fields(checkers).collect(),
+            pins: players.map(|player| board.pins(player)),
+            attack_counts: players.map(|player| board.attack_counts(player).to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(name: &str) -> usize {
+        pos_from_algebraic(name).unwrap()
+    }
+
+    #[test]
+    fn checkers_and_pins() {
+        let game_state = GameState::from_fen("4r2k/8/8/8/1b6/2N5/4P3/r3K1Rq w - - 0 1").unwrap();
+        let board = &game_state.board;
+        assert_eq!(board.checkers(Player::White), bit(pos("a1")));
+        assert_eq!(board.attackers(pos("g1"), Player::Black), bit(pos("h1")));
+        let pins = board.pins(Player::White);
+        assert_eq!(
+            pins,
+            vec![
+                Pin { pinned: pos("g1"), pinner: pos("h1"), ray: 0xE0 },
+                Pin { pinned: pos("e2"), pinner: pos("e8"), ray: 0x1010_1010_1010_1000 },
+                Pin {
+                    pinned: pos("c3"),
+                    pinner: pos("b4"),
+                    ray: bit(pos("b4")) | bit(pos("c3")) | bit(pos("d2")),
+                },
+            ]
+        );
+        let counts = board.attack_counts(Player::Black);
+        assert_eq!(counts[pos("d1")], 1);
+        assert_eq!(counts[pos("e1")], 1);
+        assert_eq!(counts[pos("c3")], 1);
+        assert_eq!(counts[pos("d2")], 0);
+    }
+}
@@ -0,0 +1,168 @@
+// Bitboards: one bit per field, bit 0 is a1, bit 7 is h1 and bit 63 is h8
+// (the same indices as BoardState.fields). The attack tables for knights,
+// kings and pawns are precomputed at compile time. Sliding pieces use
+// precomputed rays which are cut off behind the first blocker.
+
+pub type Bitboard = u64;
+
+// (row, col) steps in the same convention as game::DIRECTIONS
+const KNIGHT_STEPS: [(i32, i32); 8] = [
+    (2, 1),
+    (1, 2),
+    (-1, 2),
+    (-2, 1),
+    (-2, -1),
+    (-1, -2),
+    (1, -2),
+    (2, -1),
+];
+const KING_STEPS: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+const WHITE_PAWN_CAPTURE_STEPS: [(i32, i32); 2] = [(1, 1), (1, -1)];
+const BLACK_PAWN_CAPTURE_STEPS: [(i32, i32); 2] = [(-1, 1), (-1, -1)];
+
+// Ray directions. The first four increase the field index when stepping
+// along them, the last four decrease it.
+const RAY_STEPS: [(i32, i32); 8] = [
+    (1, 0),
+    (0, 1),
+    (1, 1),
+    (1, -1),
+    (-1, 0),
+    (0, -1),
+    (-1, -1),
+    (-1, 1),
+];
+const NORTH: usize = 0;
+const EAST: usize = 1;
+const NORTH_EAST: usize = 2;
+const NORTH_WEST: usize = 3;
+const SOUTH: usize = 4;
+const WEST: usize = 5;
+const SOUTH_WEST: usize = 6;
+const SOUTH_EAST: usize = 7;
+
+const fn step_attacks(steps: &[(i32, i32)], max_steps: i32) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut pos = 0;
+    while pos < 64 {
+        let (row, col) = ((pos / 8) as i32, (pos % 8) as i32);
+        let mut i = 0;
+        while i < steps.len() {
+            let mut step = 1;
+            while step <= max_steps {
+                let (new_row, new_col) = (row + steps[i].0 * step, col + steps[i].1 * step);
+                if new_row >= 0 && new_row < 8 && new_col >= 0 && new_col < 8 {
+                    table[pos] |= 1 << (new_row * 8 + new_col);
+                }
+                step += 1;
+            }
+            i += 1;
+        }
+        pos += 1;
+    }
+    table
+}
+
+const fn rays() -> [[Bitboard; 64]; 8] {
+    let mut rays = [[0; 64]; 8];
+    let mut direction = 0;
+    while direction < 8 {
+        let steps = [RAY_STEPS[direction]];
+        rays[direction] = step_attacks(&steps, 7);
+        direction += 1;
+    }
+    rays
+}
+
+pub static KNIGHT_ATTACKS: [Bitboard; 64] = step_attacks(&KNIGHT_STEPS, 1);
+pub static KING_ATTACKS: [Bitboard; 64] = step_attacks(&KING_STEPS, 1);
+// Indexed by player: [black, white] like the order of game::Player
+pub static PAWN_ATTACKS: [[Bitboard; 64]; 2] = [
+    step_attacks(&BLACK_PAWN_CAPTURE_STEPS, 1),
+    step_attacks(&WHITE_PAWN_CAPTURE_STEPS, 1),
+];
+static RAYS: [[Bitboard; 64]; 8] = rays();
+
+pub const FIRST_AND_LAST_ROW: Bitboard = 0xFF00_0000_0000_00FF;
+
+// a1 is a dark field
+pub const DARK_FIELDS: Bitboard = 0xAA55_AA55_AA55_AA55;
+
+pub fn bit(pos: usize) -> Bitboard {
+    1 << pos
+}
+
+// Fields attacked along a single ray, up to and including the first blocker.
+fn ray_attacks(direction: usize, pos: usize, occupied: Bitboard) -> Bitboard {
+    let ray = RAYS[direction][pos];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let first_blocker = if direction < SOUTH {
+        blockers.trailing_zeros() as usize
+    } else {
+        63 - blockers.leading_zeros() as usize
+    };
+    ray ^ RAYS[direction][first_blocker]
+}
+
+pub fn rook_attacks(pos: usize, occupied: Bitboard) -> Bitboard {
+    ray_attacks(NORTH, pos, occupied)
+        | ray_attacks(EAST, pos, occupied)
+        | ray_attacks(SOUTH, pos, occupied)
+        | ray_attacks(WEST, pos, occupied)
+}
+
+pub fn bishop_attacks(pos: usize, occupied: Bitboard) -> Bitboard {
+    ray_attacks(NORTH_EAST, pos, occupied)
+        | ray_attacks(NORTH_WEST, pos, occupied)
+        | ray_attacks(SOUTH_EAST, pos, occupied)
+        | ray_attacks(SOUTH_WEST, pos, occupied)
+}
+
+// Iterates over the indices of all set bits, lowest first.
+pub struct Fields(Bitboard);
+
+impl Iterator for Fields {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let pos = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(pos)
+    }
+}
+
+pub fn fields(bitboard: Bitboard) -> Fields {
+    Fields(bitboard)
+}
+
+// Bitboards do not fit into JavaScript numbers, so they are serialized as a
+// list of fields, e.g. with #[serde(with = "bitboard::serde_fields")].
+pub mod serde_fields {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{bit, fields, Bitboard};
+
+    pub fn serialize<S: Serializer>(bitboard: &Bitboard, serializer: S) -> Result<S::Ok, S::Error> {
+        fields(*bitboard).collect::<Vec<usize>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bitboard, D::Error> {
+        let fields = Vec::<usize>::deserialize(deserializer)?;
+        Ok(fields.into_iter().filter(|&pos| pos < 64).fold(0, |bitboard, pos| bitboard | bit(pos)))
+    }
+}
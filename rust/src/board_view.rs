@@ -6,10 +6,11 @@ use tui::widgets::Widget;
 use std::collections::HashSet;
 
 use crate::game::*;
+use crate::square::Square;
 
 pub struct BoardView {
     pub board: BoardState,
-    pub highlights: HashSet<usize>,
+    pub highlights: HashSet<Square>,
 }
 
 impl BoardView {
@@ -19,18 +20,17 @@ impl BoardView {
             highlights: HashSet::new(),
         }
     }
-    pub fn newh(board: BoardState, highlights: HashSet<usize>) -> Self {
+    pub fn newh(board: BoardState, highlights: HashSet<Square>) -> Self {
         Self { board, highlights }
     }
 
-    pub fn get_field_index_from_pos(area: Rect, x: u16, y: u16) -> Option<usize> {
+    pub fn get_square_from_pos(area: Rect, x: u16, y: u16) -> Option<Square> {
         if x >= area.x && x < area.x + area.width && y >= area.y && y < area.y + area.height {
             let x = x - area.x;
             let y = y - area.y;
             let row = y as usize;
             let col = (x / 3) as usize;
-            let field = (7 - row) * 8 + col;
-            Some(field)
+            Square::from_file_rank(col, 7usize.checked_sub(row)?)
         } else {
             None
         }
@@ -39,16 +39,16 @@ impl BoardView {
 
 impl Widget for BoardView {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        for i in 0..64 {
-            let row = 7 - (i / 8);
-            let col = i % 8;
+        for square in Square::all() {
+            let row = 7 - square.rank();
+            let col = square.file();
             let x = (col * 3) as u16;
             let y = row as u16;
             if x >= area.width || y >= area.height {
                 continue;
             }
 
-            let text = if let Some(piece_and_player) = self.board.fields[i] {
+            let text = if let Some(piece_and_player) = self.board.field(square.index()) {
                 match piece_and_player {
                     (PieceType::InitKing, Player::Black) => " ♚ ",
                     (PieceType::King, Player::Black) => " ♚ ",
@@ -74,7 +74,7 @@ impl Widget for BoardView {
             };
 
             let base = Style::default().fg(Color::Black);
-            let color_style = match (is_dark_field(row, col), self.highlights.contains(&i)) {
+            let color_style = match (is_dark_field(row, col), self.highlights.contains(&square)) {
                 (true, false) => base.bg(Color::Rgb(100, 100, 100)),
                 (false, false) => base.bg(Color::Rgb(250, 250, 250)),
                 (true, true) => base.bg(Color::Rgb(100, 100, 0)),
@@ -87,5 +87,5 @@ impl Widget for BoardView {
 }
 
 fn is_dark_field(row: usize, col: usize) -> bool {
-    (row + col + 1) % 2 == 0
+    (row + col + 1).is_multiple_of(2)
 }
@@ -0,0 +1,112 @@
+// Chess960 (Fischer Random) start positions. The 960 possible back rows are
+// numbered like in Scharnagl's scheme, so index 518 is the standard position.
+// Castling in these positions is handled by the regular move generator which
+// follows the Chess960 castling rules.
+
+use rand::Rng;
+
+use crate::game::*;
+use crate::variant::Variant;
+
+// The two fields of the five remaining ones the knights stand on.
+const KNIGHT_PLACEMENTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+pub const NUM_POSITIONS: usize = 960;
+
+pub const STANDARD_POSITION: usize = 518;
+
+// The n-th empty field of the row.
+fn nth_empty(row: &[Option<PieceType>; 8], n: usize) -> usize {
+    (0..8)
+        .filter(|&col| row[col].is_none())
+        .nth(n)
+        .expect("There are not enough empty fields left.")
+}
+
+pub fn back_row(index: usize) -> Option<[PieceType; 8]> {
+    if index >= NUM_POSITIONS {
+        return None;
+    }
+    let mut row = [None; 8];
+    // Bishops on fields of opposite color, then the queen, the knights and
+    // the king between the rooks on the remaining three fields.
+    let mut n = index;
+    row[(n % 4) * 2 + 1] = Some(PieceType::Bishop);
+    n /= 4;
+    row[(n % 4) * 2] = Some(PieceType::Bishop);
+    n /= 4;
+    row[nth_empty(&row, n % 6)] = Some(PieceType::Queen);
+    n /= 6;
+    let (first_knight, second_knight) = KNIGHT_PLACEMENTS[n];
+    // Place the second one first so that the first one's index stays valid.
+    row[nth_empty(&row, second_knight)] = Some(PieceType::Knight);
+    row[nth_empty(&row, first_knight)] = Some(PieceType::Knight);
+    let king_between_rooks = [PieceType::InitRook, PieceType::InitKing, PieceType::InitRook];
+    for &piece in king_between_rooks.iter() {
+        row[nth_empty(&row, 0)] = Some(piece);
+    }
+
+    let mut back_row = [PieceType::InitRook; 8];
+    for (field, piece) in back_row.iter_mut().zip(row.iter()) {
+        *field = piece.expect("All fields are filled.");
+    }
+    Some(back_row)
+}
+
+impl BoardState {
+    pub fn new_chess960(index: usize) -> Option<Self> {
+        back_row(index).map(BoardState::from_back_rank)
+    }
+}
+
+// Standard rules, but every game starts from a random one of the positions.
+pub struct Chess960;
+
+impl Variant for Chess960 {
+    fn name(&self) -> &'static str {
+        "Chess960"
+    }
+
+    fn start_position(&self) -> BoardState {
+        let index = rand::thread_rng().gen_range(0, NUM_POSITIONS);
+        BoardState::new_chess960(index).expect("The index is in range.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_positions() {
+        assert!(BoardState::new_chess960(STANDARD_POSITION) == Some(BoardState::new()));
+        assert!(BoardState::new_chess960(NUM_POSITIONS).is_none());
+        let mut back_rows: Vec<[PieceType; 8]> = (0..NUM_POSITIONS).filter_map(back_row).collect();
+        back_rows.sort_by_key(|row| format!("{:?}", row));
+        back_rows.dedup();
+        assert_eq!(back_rows.len(), NUM_POSITIONS);
+    }
+
+    #[test]
+    fn castling_rights_round_trip() {
+        // Two rooks on the queen's side, only the inner one may castle.
+        let fen = "1r2k1r1/8/8/8/8/8/8/RR2K2R w KBk - 0 1";
+        let game_state = GameState::from_fen(fen).unwrap();
+        assert_eq!(game_state.to_fen(), fen);
+        assert_eq!(
+            GameState::from_fen("1r2k1r1/8/8/8/8/8/8/RR2K2R w HBg - 0 1").unwrap().to_fen(),
+            fen
+        );
+    }
+}
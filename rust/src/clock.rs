@@ -0,0 +1,384 @@
+// Chess clocks. A time control consists of stages, e.g. 40 moves in 90
+// minutes and then 30 minutes for the rest of the game, each with its own
+// increment or delay. The clock does not read the time itself because there
+// is no common timer for the TUI and wasm: the caller measures how long a
+// move took and passes it to press(). All times are in milliseconds.
+//
+// Time controls are written like the PGN TimeControl tag, in seconds:
+// "300" (sudden death), "300+2" (Fischer increment), "40/5400:1800+30"
+// (stages separated by ':'), plus "300d2" for a simple delay and "300b2"
+// for a Bronstein delay.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::*;
+
+pub type Millis = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Delay {
+    None,
+    // Added after every move.
+    Increment(Millis),
+    // The clock runs, afterwards the time used is given back up to the delay.
+    Bronstein(Millis),
+    // The clock only starts after the delay.
+    Simple(Millis),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stage {
+    // The number of moves to play in this stage, None for the rest of the
+    // game.
+    pub moves: Option<u32>,
+    pub time: Millis,
+    pub delay: Delay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub stages: Vec<Stage>,
+}
+
+impl TimeControl {
+    pub fn new(time: Millis, delay: Delay) -> Self {
+        TimeControl {
+            stages: vec![Stage {
+                moves: None,
+                time,
+                delay,
+            }],
+        }
+    }
+
+    pub fn sudden_death(time: Millis) -> Self {
+        Self::new(time, Delay::None)
+    }
+
+    // The last stage is repeated if it ends after a number of moves.
+    pub fn stage(&self, index: usize) -> &Stage {
+        &self.stages[index.min(self.stages.len() - 1)]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseTimeControlError(pub String);
+
+impl fmt::Display for ParseTimeControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not a time control like 300+2 or 40/5400:1800", self.0)
+    }
+}
+
+impl std::error::Error for ParseTimeControlError {}
+
+// Far more than any game lasts, more is most likely a typo.
+const MAX_SECONDS: f64 = 10_000_000.0;
+
+// Plain decimals like 300 or 0.5, no signs or exponents.
+fn parse_seconds(s: &str) -> Option<Millis> {
+    if !s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let seconds: f64 = s.parse().ok()?;
+    if seconds <= MAX_SECONDS {
+        Some((seconds * 1000.0).round() as Millis)
+    } else {
+        None
+    }
+}
+
+fn parse_stage(s: &str) -> Option<Stage> {
+    let (moves, rest) = match s.split_once('/') {
+        Some((moves, rest)) => (Some(moves.parse().ok().filter(|&moves| moves > 0)?), rest),
+        None => (None, s),
+    };
+    let (time, delay) = match rest.find(['+', 'b', 'd']) {
+        Some(i) => {
+            let delay = parse_seconds(&rest[i + 1..])?;
+            let delay = match &rest[i..=i] {
+                "+" => Delay::Increment(delay),
+                "b" => Delay::Bronstein(delay),
+                _ => Delay::Simple(delay),
+            };
+            (&rest[..i], delay)
+        }
+        None => (rest, Delay::None),
+    };
+    Some(Stage {
+        moves,
+        time: parse_seconds(time)?,
+        delay,
+    })
+}
+
+impl FromStr for TimeControl {
+    type Err = ParseTimeControlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stages: Option<Vec<Stage>> = s.trim().split(':').map(parse_stage).collect();
+        match stages {
+            Some(stages) if !stages.is_empty() => Ok(TimeControl { stages }),
+            _ => Err(ParseTimeControlError(s.to_string())),
+        }
+    }
+}
+
+fn format_seconds(time: Millis) -> String {
+    if time.is_multiple_of(1000) {
+        (time / 1000).to_string()
+    } else {
+        format!("{}", time as f64 / 1000.0)
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<String> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                let moves = stage.moves.map_or(String::new(), |moves| format!("{}/", moves));
+                let delay = match stage.delay {
+                    Delay::None => String::new(),
+                    Delay::Increment(time) => format!("+{}", format_seconds(time)),
+                    Delay::Bronstein(time) => format!("b{}", format_seconds(time)),
+                    Delay::Simple(time) => format!("d{}", format_seconds(time)),
+                };
+                format!("{}{}{}", moves, format_seconds(stage.time), delay)
+            })
+            .collect();
+        write!(f, "{}", stages.join(":"))
+    }
+}
+
+// The clock of both players, indexed by Player. Only the player to move
+// uses time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clock {
+    pub control: TimeControl,
+    pub turn: Player,
+    remaining: [Millis; 2],
+    stage: [usize; 2],
+    // The moves made in the current stage.
+    stage_moves: [u32; 2],
+    flagged: Option<Player>,
+}
+
+impl Clock {
+    // The first player to move is usually White, but a game may start from
+    // any position.
+    pub fn new(control: TimeControl, turn: Player) -> Self {
+        let time = control.stage(0).time;
+        Clock {
+            control,
+            turn,
+            remaining: [time; 2],
+            stage: [0; 2],
+            stage_moves: [0; 2],
+            flagged: None,
+        }
+    }
+
+    // The time of player at the start of its turn.
+    pub fn remaining(&self, player: Player) -> Millis {
+        self.remaining[player as usize]
+    }
+
+    pub fn flagged(&self) -> Option<Player> {
+        self.flagged
+    }
+
+    fn delay(&self, player: Player) -> Delay {
+        self.control.stage(self.stage[player as usize]).delay
+    }
+
+    // The time which counts against the clock if the player to move has
+    // been thinking for elapsed.
+    fn used(&self, elapsed: Millis) -> Millis {
+        match self.delay(self.turn) {
+            Delay::Simple(delay) => elapsed.saturating_sub(delay),
+            _ => elapsed,
+        }
+    }
+
+    // What the clock shows for both players while the player to move has
+    // been thinking for elapsed.
+    pub fn display(&self, elapsed: Millis) -> [Millis; 2] {
+        let mut display = self.remaining;
+        let turn = self.turn as usize;
+        display[turn] = display[turn].saturating_sub(self.used(elapsed));
+        display
+    }
+
+    // The moves until the next stage starts, None in the last stage.
+    pub fn moves_to_go(&self, player: Player) -> Option<u32> {
+        let stage = self.control.stage(self.stage[player as usize]);
+        stage.moves.map(|moves| moves.saturating_sub(self.stage_moves[player as usize]))
+    }
+
+    // Whether the flag of the player to move has fallen after thinking for
+    // elapsed. The flag stays down.
+    pub fn check_flag(&mut self, elapsed: Millis) -> bool {
+        if self.flagged.is_none() && self.used(elapsed) > self.remaining(self.turn) {
+            self.remaining[self.turn as usize] = 0;
+            self.flagged = Some(self.turn);
+        }
+        self.flagged.is_some()
+    }
+
+    // The player to move finished its move after elapsed. Returns false
+    // without changing the turn if the flag fell before.
+    pub fn press(&mut self, elapsed: Millis) -> bool {
+        if self.check_flag(elapsed) {
+            return false;
+        }
+        let player = self.turn as usize;
+        let bonus = match self.delay(self.turn) {
+            Delay::Increment(increment) => increment,
+            Delay::Bronstein(delay) => elapsed.min(delay),
+            Delay::None | Delay::Simple(_) => 0,
+        };
+        self.remaining[player] =
+            (self.remaining[player] - self.used(elapsed)).saturating_add(bonus);
+        self.stage_moves[player] += 1;
+        if self.moves_to_go(self.turn) == Some(0) {
+            self.stage[player] += 1;
+            self.stage_moves[player] = 0;
+            let time = self.control.stage(self.stage[player]).time;
+            self.remaining[player] = self.remaining[player].saturating_add(time);
+        }
+        self.turn = self.turn.opponent();
+        true
+    }
+
+    // A simple time budget for an engine: an equal share of the remaining
+    // time for the moves of the stage (30 if the stage never ends), plus
+    // what is given back after the move.
+    pub fn think_time(&self) -> Millis {
+        let remaining = self.remaining(self.turn);
+        let moves = self.moves_to_go(self.turn).unwrap_or(30).max(1) as Millis;
+        let bonus = match self.delay(self.turn) {
+            Delay::None => 0,
+            Delay::Increment(time) | Delay::Bronstein(time) | Delay::Simple(time) => time,
+        };
+        (remaining / moves).saturating_add(bonus).min(remaining / 2)
+    }
+
+    // The clock part of the UCI "go" command. Delays are sent as increments
+    // since UCI does not know them.
+    pub fn uci_go(&self) -> String {
+        let bonus = |player: Player| match self.delay(player) {
+            Delay::None => 0,
+            Delay::Increment(time) | Delay::Bronstein(time) | Delay::Simple(time) => time,
+        };
+        let mut go = format!(
+            "go wtime {} btime {} winc {} binc {}",
+            self.remaining(Player::White),
+            self.remaining(Player::Black),
+            bonus(Player::White),
+            bonus(Player::Black)
+        );
+        if let Some(moves) = self.moves_to_go(self.turn) {
+            go.push_str(&format!(" movestogo {}", moves));
+        }
+        go
+    }
+}
+
+// The winner when the flag of a player falls: the opponent, unless it can
+// not win at all any more (timeout vs insufficient material), which is a
+// draw.
+pub fn flag_winner(game_state: &GameState, flagged: Player) -> Option<Player> {
+    let opponent = flagged.opponent();
+    if game_state.variant.rules().can_win(game_state, opponent) {
+        Some(opponent)
+    } else {
+        None
+    }
+}
+
+// Minutes and seconds, with tenths in the last 10 seconds.
+pub fn format_clock(time: Millis) -> String {
+    if time < 10_000 {
+        format!("0:{:02}.{}", time / 1000, time % 1000 / 100)
+    } else {
+        let seconds = time / 1000;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::VariantKind;
+
+    #[test]
+    fn time_controls() {
+        for s in ["300", "300+2", "300d5", "180b2", "40/5400:1800+30", "40/7200", "0.5+0.1"] {
+            assert_eq!(s.parse::<TimeControl>().unwrap().to_string(), s);
+        }
+        let invalid = ["", "-", "40/", "0/300", "300+", "300x2", "40/5400:", "1e300", "300+1e3", "inf", "2e7", "20000000"];
+        for s in invalid {
+            assert!(s.parse::<TimeControl>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn delays_and_stages() {
+        let mut clock = Clock::new("10+2".parse().unwrap(), Player::White);
+        assert!(clock.press(3_000));
+        assert_eq!(clock.remaining(Player::White), 9_000);
+
+        let mut clock = Clock::new("10d2".parse().unwrap(), Player::White);
+        assert_eq!(clock.display(3_000), [10_000, 9_000]);
+        assert!(clock.press(3_000));
+        assert!(clock.press(1_000));
+        assert_eq!(clock.remaining(Player::White), 9_000);
+        assert_eq!(clock.remaining(Player::Black), 10_000);
+
+        let mut clock = Clock::new("10b2".parse().unwrap(), Player::White);
+        assert_eq!(clock.display(3_000)[Player::White as usize], 7_000);
+        assert!(clock.press(3_000));
+        assert_eq!(clock.remaining(Player::White), 9_000);
+
+        let mut clock = Clock::new("2/10:5".parse().unwrap(), Player::White);
+        for _ in 0..3 {
+            assert!(clock.press(1_000));
+        }
+        assert_eq!(clock.moves_to_go(Player::Black), Some(1));
+        assert!(clock.press(1_000));
+        assert_eq!(clock.remaining(Player::White), 13_000);
+        assert_eq!(clock.moves_to_go(Player::White), None);
+        assert!(!clock.press(14_000));
+        assert_eq!(clock.flagged(), Some(Player::White));
+        assert_eq!(clock.turn, Player::White);
+    }
+
+    #[test]
+    fn timeout_vs_insufficient_material() {
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+        assert_eq!(flag_winner(&game_state, Player::Black), Some(Player::White));
+        assert_eq!(flag_winner(&game_state, Player::White), None);
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/3NK3 b - - 0 1").unwrap();
+        assert_eq!(flag_winner(&game_state, Player::Black), None);
+        let game_state = GameState::from_fen("4k3/3p4/8/8/8/8/8/3NK3 b - - 0 1").unwrap();
+        assert_eq!(flag_winner(&game_state, Player::Black), Some(Player::White));
+        let fen = "4k3/8/8/8/8/8/8/3NK3 b - - 0 1";
+        let game_state = GameState::from_fen_with_variant(fen, VariantKind::ThreeCheck).unwrap();
+        assert_eq!(flag_winner(&game_state, Player::Black), Some(Player::White));
+        let game_state = GameState::from_fen_with_variant(fen, VariantKind::Atomic).unwrap();
+        assert_eq!(flag_winner(&game_state, Player::Black), None);
+        // Bishops on fields of one color, but one can blow up the other king.
+        let fen = "2b1k3/8/8/8/8/8/8/3BK3 b - - 0 1";
+        assert_eq!(flag_winner(&GameState::from_fen(fen).unwrap(), Player::Black), None);
+        let game_state = GameState::from_fen_with_variant(fen, VariantKind::Atomic).unwrap();
+        assert_eq!(flag_winner(&game_state, Player::Black), Some(Player::White));
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1";
+        let game_state = GameState::from_fen_with_variant(fen, VariantKind::KingOfTheHill).unwrap();
+        assert_eq!(flag_winner(&game_state, Player::White), Some(Player::Black));
+    }
+}
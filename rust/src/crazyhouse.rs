@@ -0,0 +1,176 @@
+// Crazyhouse: Captured pieces change sides and go to the capturer's pocket.
+// Instead of moving a piece, a player may drop a piece from the pocket on any
+// empty field, except for pawns on the first and last row. Promoted pieces
+// turn back into pawns when they are captured, so GameState keeps track of
+// them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::bitboard::{self, bit, FIRST_AND_LAST_ROW};
+use crate::game::*;
+use crate::variant::{automatic_draw, Variant};
+
+// The pieces which can be dropped, in the order they are shown.
+pub const DROP_PIECES: [PieceType; 5] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Pawn,
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Pocket {
+    pub queens: usize,
+    pub rooks: usize,
+    pub bishops: usize,
+    pub knights: usize,
+    pub pawns: usize,
+}
+
+impl Pocket {
+    fn count_mut(&mut self, piece: PieceType) -> &mut usize {
+        match piece {
+            PieceType::Queen => &mut self.queens,
+            PieceType::InitRook | PieceType::Rook => &mut self.rooks,
+            PieceType::Bishop => &mut self.bishops,
+            PieceType::Knight => &mut self.knights,
+            PieceType::InitPawn | PieceType::Pawn => &mut self.pawns,
+            PieceType::InitKing | PieceType::King => panic!("Kings can not be put into the pocket."),
+        }
+    }
+
+    pub fn count(&self, piece: PieceType) -> usize {
+        match piece {
+            PieceType::Queen => self.queens,
+            PieceType::InitRook | PieceType::Rook => self.rooks,
+            PieceType::Bishop => self.bishops,
+            PieceType::Knight => self.knights,
+            PieceType::InitPawn | PieceType::Pawn => self.pawns,
+            PieceType::InitKing | PieceType::King => 0,
+        }
+    }
+
+    pub fn add(&mut self, piece: PieceType) {
+        *self.count_mut(piece) += 1;
+    }
+
+    pub fn remove(&mut self, piece: PieceType) {
+        let count = self.count_mut(piece);
+        *count = count.checked_sub(1).expect("The piece is not in the pocket.");
+    }
+
+    pub fn len(&self) -> usize {
+        DROP_PIECES.iter().map(|&piece| self.count(piece)).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Pocket::default()
+    }
+}
+
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn name(&self) -> &'static str {
+        "Crazyhouse"
+    }
+
+    fn uses_pockets(&self) -> bool {
+        true
+    }
+
+    // Captured pieces come back, so there is never insufficient material.
+    fn game_end(&self, game_state: &GameState) -> Option<FinishedState> {
+        automatic_draw(game_state)
+    }
+}
+
+impl GameState {
+    // Pseudo legal drops of the player to move.
+    pub fn get_drop_moves(&self) -> Vec<Move> {
+        let pocket = self.pockets[self.turn() as usize];
+        let empty = !self.board.occupied();
+        let mut moves = Vec::new();
+        for &piece in DROP_PIECES.iter() {
+            if pocket.count(piece) == 0 {
+                continue;
+            }
+            let targets = if piece == PieceType::Pawn {
+                empty & !FIRST_AND_LAST_ROW
+            } else {
+                empty
+            };
+            moves.extend(bitboard::fields(targets).map(|pos| Move::new_drop(piece, pos)));
+        }
+        moves
+    }
+
+    // Moves the pieces between the board and the pockets for a move of the
+    // player to move which has already been applied to the board.
+    pub(crate) fn update_pockets(&mut self, mv: Move, captured: Option<(PieceType, Player)>) {
+        let player = self.turn() as usize;
+        if let Some(piece) = mv.drop {
+            self.pockets[player].remove(piece);
+            return;
+        }
+        if let Some((piece, _)) = captured {
+            // En passant always captures a real pawn.
+            let promoted = !mv.en_passant && self.promoted & bit(mv.to) != 0;
+            let piece = if promoted { PieceType::Pawn } else { piece };
+            self.pockets[player].add(piece);
+        }
+        let moves_promoted = mv.promotion.is_some() || self.promoted & bit(mv.from) != 0;
+        self.promoted &= !(bit(mv.from) | bit(mv.to));
+        if moves_promoted {
+            self.promoted |= bit(mv.to);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::VariantKind;
+
+    fn play(game_state: &mut GameState, san: &str) -> (Move, UndoInfo) {
+        let mv = game_state.parse_san(san).unwrap();
+        (mv, game_state.make_move(mv))
+    }
+
+    #[test]
+    fn promoted_pieces_turn_back_into_pawns() {
+        let mut game_state =
+            GameState::from_fen_with_variant("4k3/1P6/8/8/8/7r/8/4K3 w - - 0 1", VariantKind::Crazyhouse).unwrap();
+        for san in ["b8=Q+", "Kd7", "Qb7+", "Ke8", "Kf2", "Rh2+", "Kg3", "Rb2", "Kf3"].iter() {
+            play(&mut game_state, san);
+        }
+        assert_eq!(game_state.promoted, bit(pos_from_algebraic("b7").unwrap()));
+        let mut unpromoted = game_state.clone();
+        unpromoted.promoted = 0;
+        assert_ne!(game_state.position_key(), unpromoted.position_key());
+        let before = game_state.clone();
+        let (mv, undo) = play(&mut game_state, "Rxb7");
+        assert_eq!(game_state.pockets[Player::Black as usize], Pocket { pawns: 1, ..Pocket::default() });
+        assert_eq!(game_state.promoted, 0);
+        game_state.unmake_move(mv, undo);
+        assert_eq!(game_state, before);
+    }
+
+    #[test]
+    fn drops() {
+        let mut game_state =
+            GameState::from_fen_with_variant("4k3/1r6/8/8/5K2/8/8/8 w - - 0 1", VariantKind::Crazyhouse).unwrap();
+        assert!(game_state.get_drop_moves().is_empty());
+        game_state.pockets[Player::White as usize].add(PieceType::Pawn);
+        // No pawns on the first and last row.
+        assert_eq!(game_state.get_drop_moves().len(), 48 - 2);
+        let drop = game_state.parse_san("@e3").unwrap();
+        assert_eq!(game_state.to_san(drop), "P@e3");
+        assert_eq!(game_state.to_uci(drop), "P@e3");
+        assert_eq!(game_state.parse_uci("P@e3"), Ok(drop));
+        game_state.make_move(drop);
+        assert!(game_state.pockets[Player::White as usize].is_empty());
+        assert_eq!(game_state.board.field(pos_from_algebraic("e3").unwrap()), Some((PieceType::Pawn, Player::White)));
+    }
+}
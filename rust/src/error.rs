@@ -0,0 +1,68 @@
+// The errors of the library. Everything which comes from the outside (JSON
+// from the browser, user input) is checked and reported with a ChaiError so
+// that the front ends can display it instead of crashing.
+
+use std::fmt;
+
+use crate::clock::ParseTimeControlError;
+use crate::game::*;
+use crate::validation::PositionError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChaiError {
+    Json(String),
+    InvalidPosition(Vec<PositionError>),
+    UnknownVariant(String),
+    InvalidField(usize),
+    NoKing(Player),
+    IllegalMove(Move),
+    NoLegalMoves,
+    InvalidNode(usize),
+    InvalidPgn(String),
+    InvalidTimeControl(String),
+    GameOver,
+    NoDrawOffer,
+    NoDrawClaim,
+}
+
+impl fmt::Display for ChaiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChaiError::Json(message) => write!(f, "malformed game state: {}", message),
+            ChaiError::InvalidPosition(errors) => {
+                let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "impossible position: {}", errors.join(", "))
+            }
+            ChaiError::UnknownVariant(name) => write!(f, "unknown variant '{}'", name),
+            ChaiError::InvalidField(field) => write!(f, "there is no field {}", field),
+            ChaiError::NoKing(player) => write!(f, "{:?} has no king", player),
+            ChaiError::IllegalMove(mv) => write!(
+                f,
+                "the move from {} to {} is not legal",
+                pos_to_algebraic(mv.from),
+                pos_to_algebraic(mv.to)
+            ),
+            ChaiError::NoLegalMoves => write!(f, "there are no legal moves, the game is over"),
+            ChaiError::InvalidNode(node) => write!(f, "node {} is not part of the game tree", node),
+            ChaiError::InvalidPgn(message) => write!(f, "invalid PGN: {}", message),
+            ChaiError::InvalidTimeControl(message) => write!(f, "invalid time control: {}", message),
+            ChaiError::GameOver => write!(f, "the game is already over"),
+            ChaiError::NoDrawOffer => write!(f, "the opponent has not offered a draw"),
+            ChaiError::NoDrawClaim => write!(f, "there is no draw to claim in this position"),
+        }
+    }
+}
+
+impl std::error::Error for ChaiError {}
+
+impl From<ParseTimeControlError> for ChaiError {
+    fn from(error: ParseTimeControlError) -> Self {
+        ChaiError::InvalidTimeControl(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ChaiError {
+    fn from(error: serde_json::Error) -> Self {
+        ChaiError::Json(error.to_string())
+    }
+}
@@ -0,0 +1,404 @@
+// Forsyth-Edwards Notation (FEN) import and export for GameState.
+//
+// FEN describes castling rights, the en passant square and the move counters
+// explicitly while GameState encodes them implicitly: Castling rights are
+// InitKing/InitRook pieces, the en passant square is the EnPassantFieldInfo
+// and the counters are derived from ply and fifty_move_rule_last_event.
+// The functions in here translate between both worlds.
+
+use std::fmt;
+use std::ops;
+
+use crate::crazyhouse::Pocket;
+use crate::game::*;
+use crate::square::Square;
+use crate::validation::PositionError;
+use crate::variant::VariantKind;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FenError {
+    WrongNumberOfFields(usize),
+    WrongNumberOfRanks(usize),
+    InvalidRankLength { rank: usize, length: usize },
+    InvalidPiece(char),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    CastlingWithoutKing(char),
+    CastlingWithoutRook(char),
+    InvalidEnPassant(String),
+    EnPassantWithoutPawn(String),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    InvalidPosition(Vec<PositionError>),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::WrongNumberOfFields(n) => {
+                write!(f, "expected 6 space-separated fields, found {}", n)
+            }
+            FenError::WrongNumberOfRanks(n) => write!(f, "expected 8 ranks, found {}", n),
+            FenError::InvalidRankLength { rank, length } => write!(
+                f,
+                "rank {} describes {} squares instead of 8",
+                rank, length
+            ),
+            FenError::InvalidPiece(c) => write!(f, "invalid piece character '{}'", c),
+            FenError::InvalidSideToMove(s) => {
+                write!(f, "invalid side to move '{}', expected 'w' or 'b'", s)
+            }
+            FenError::InvalidCastling(s) => write!(f, "invalid castling field '{}'", s),
+            FenError::CastlingWithoutKing(c) => write!(
+                f,
+                "castling right '{}' given but there is no king on the back rank",
+                c
+            ),
+            FenError::CastlingWithoutRook(c) => write!(
+                f,
+                "castling right '{}' given but there is no rook to castle with",
+                c
+            ),
+            FenError::InvalidEnPassant(s) => write!(f, "invalid en passant square '{}'", s),
+            FenError::EnPassantWithoutPawn(s) => write!(
+                f,
+                "en passant square '{}' given but there is no pawn which just double-stepped",
+                s
+            ),
+            FenError::InvalidHalfmoveClock(s) => write!(f, "invalid halfmove clock '{}'", s),
+            FenError::InvalidFullmoveNumber(s) => write!(f, "invalid fullmove number '{}'", s),
+            FenError::InvalidPosition(errors) => {
+                let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "impossible position: {}", errors.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+// The fields of the row a player castles on, from the a-file to the h-file.
+// Far more than any game lasts, the counters only need to stay clear of
+// overflows when moves are made.
+const MAX_PLY: usize = i32::MAX as usize;
+
+fn back_row(player: Player) -> ops::Range<usize> {
+    match player {
+        Player::White => 0..8,
+        Player::Black => 56..64,
+    }
+}
+
+fn is_king(field: Option<(PieceType, Player)>, player: Player) -> bool {
+    matches!(field, Some((PieceType::King, p)) | Some((PieceType::InitKing, p)) if p == player)
+}
+
+fn is_rook(field: Option<(PieceType, Player)>, player: Player) -> bool {
+    matches!(field, Some((PieceType::Rook, p)) | Some((PieceType::InitRook, p)) if p == player)
+}
+
+// The fields on one side of the king, starting at the board edge.
+fn castling_side(player: Player, king_pos: usize, kingside: bool) -> Vec<usize> {
+    let row = back_row(player);
+    if kingside {
+        (king_pos + 1..row.end).rev().collect()
+    } else {
+        (row.start..king_pos).collect()
+    }
+}
+
+// One castling right in X-FEN: KQkq mean the outermost rook on the king's or
+// queen's side, a file letter (also used by Shredder-FEN) means the rook on
+// that file which is needed for Chess960 positions with two rooks on a side.
+fn parse_castling_right(
+    fields: &mut [Option<(PieceType, Player)>; 64],
+    c: char,
+    castling: &str,
+) -> Result<(), FenError> {
+    let player = if c.is_ascii_uppercase() {
+        Player::White
+    } else {
+        Player::Black
+    };
+    let king_pos = back_row(player)
+        .find(|&pos| is_king(fields[pos], player))
+        .ok_or(FenError::CastlingWithoutKing(c))?;
+    let rook_pos = match c.to_ascii_lowercase() {
+        'k' => castling_side(player, king_pos, true)
+            .into_iter()
+            .find(|&pos| is_rook(fields[pos], player)),
+        'q' => castling_side(player, king_pos, false)
+            .into_iter()
+            .find(|&pos| is_rook(fields[pos], player)),
+        file @ 'a'..='h' => Some(back_row(player).start + (file as usize - 'a' as usize))
+            .filter(|&pos| is_rook(fields[pos], player)),
+        _ => return Err(FenError::InvalidCastling(castling.to_string())),
+    }
+    .ok_or(FenError::CastlingWithoutRook(c))?;
+    fields[king_pos] = Some((PieceType::InitKing, player));
+    fields[rook_pos] = Some((PieceType::InitRook, player));
+    Ok(())
+}
+
+fn piece_from_char(c: char) -> Option<(PieceType, Player)> {
+    let player = if c.is_ascii_uppercase() {
+        Player::White
+    } else {
+        Player::Black
+    };
+    let piece = match c.to_ascii_lowercase() {
+        'k' => PieceType::King,
+        'q' => PieceType::Queen,
+        'r' => PieceType::Rook,
+        'b' => PieceType::Bishop,
+        'n' => PieceType::Knight,
+        'p' => PieceType::Pawn,
+        _ => return None,
+    };
+    Some((piece, player))
+}
+
+fn piece_to_char(piece: PieceType, player: Player) -> char {
+    let c = match piece {
+        PieceType::InitKing | PieceType::King => 'k',
+        PieceType::Queen => 'q',
+        PieceType::InitRook | PieceType::Rook => 'r',
+        PieceType::Bishop => 'b',
+        PieceType::Knight => 'n',
+        PieceType::InitPawn | PieceType::Pawn => 'p',
+    };
+    match player {
+        Player::White => c.to_ascii_uppercase(),
+        Player::Black => c,
+    }
+}
+
+fn parse_placement(placement: &str) -> Result<[Option<(PieceType, Player)>; 64], FenError> {
+    let mut fields = [None; 64];
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::WrongNumberOfRanks(ranks.len()));
+    }
+    for (i, rank_str) in ranks.iter().enumerate() {
+        let row = 7 - i;
+        let mut col = 0;
+        for c in rank_str.chars() {
+            if let Some(empty) = c.to_digit(10).filter(|d| (1..=8).contains(d)) {
+                col += empty as usize;
+            } else {
+                let (piece, player) = piece_from_char(c).ok_or(FenError::InvalidPiece(c))?;
+                if col < 8 {
+                    // Pawns on their initial row have not moved yet and may
+                    // do a double step.
+                    let piece = match (piece, player, row) {
+                        (PieceType::Pawn, Player::White, 1) | (PieceType::Pawn, Player::Black, 6) => {
+                            PieceType::InitPawn
+                        }
+                        _ => piece,
+                    };
+                    fields[row * 8 + col] = Some((piece, player));
+                }
+                col += 1;
+            }
+        }
+        if col != 8 {
+            return Err(FenError::InvalidRankLength {
+                rank: row + 1,
+                length: col,
+            });
+        }
+    }
+    Ok(fields)
+}
+
+impl GameState {
+    pub fn from_fen(fen: &str) -> Result<GameState, FenError> {
+        GameState::from_fen_with_variant(fen, VariantKind::Standard)
+    }
+
+    // The position is validated against the rules of the variant, e.g. in
+    // Antichess there may be any number of kings.
+    pub fn from_fen_with_variant(fen: &str, variant: VariantKind) -> Result<GameState, FenError> {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        if parts.len() != 6 {
+            return Err(FenError::WrongNumberOfFields(parts.len()));
+        }
+        let mut fields = parse_placement(parts[0])?;
+
+        let turn = match parts[1] {
+            "w" => Player::White,
+            "b" => Player::Black,
+            s => return Err(FenError::InvalidSideToMove(s.to_string())),
+        };
+
+        if parts[2] != "-" {
+            for c in parts[2].chars() {
+                parse_castling_right(&mut fields, c, parts[2])?;
+            }
+        }
+
+        let invalid_fullmove = || FenError::InvalidFullmoveNumber(parts[5].to_string());
+        let invalid_halfmove = || FenError::InvalidHalfmoveClock(parts[4].to_string());
+        let fullmove: usize = match parts[5].parse() {
+            Ok(n) if n >= 1 => n,
+            _ => return Err(invalid_fullmove()),
+        };
+        let turn_offset = match turn {
+            Player::White => 0,
+            Player::Black => 1,
+        };
+        let ply = (fullmove - 1)
+            .checked_mul(2)
+            .and_then(|ply| ply.checked_add(turn_offset))
+            .filter(|&ply| ply <= MAX_PLY)
+            .ok_or_else(invalid_fullmove)?;
+        let halfmove_clock: usize = parts[4].parse().map_err(|_| invalid_halfmove())?;
+        // Set-up positions often start counting moves at 1 even though the
+        // halfmove clock says more moves have been played. The fullmove
+        // number is moved forward as far as the clock needs.
+        let clock_ply = halfmove_clock
+            .checked_add((halfmove_clock % 2 != turn_offset) as usize)
+            .filter(|&ply| ply <= MAX_PLY)
+            .ok_or_else(invalid_halfmove)?;
+        let ply = ply.max(clock_ply);
+
+        let en_passant_field = if parts[3] == "-" {
+            None
+        } else {
+            let skipped: Square = parts[3]
+                .parse()
+                .map_err(|_| FenError::InvalidEnPassant(parts[3].to_string()))?;
+            // The skipped field lies behind the pawn which just moved, i.e.
+            // the pawn of the player who is not to move.
+            let (skipped_rank, target_rank) = match turn {
+                Player::Black => (2, 3),
+                Player::White => (5, 4),
+            };
+            if skipped.rank() != skipped_rank {
+                return Err(FenError::InvalidEnPassant(parts[3].to_string()));
+            }
+            let target = Square::at(target_rank * 8 + skipped.file());
+            if fields[target.index()] != Some((PieceType::Pawn, turn.opponent())) || ply == 0 {
+                return Err(FenError::EnPassantWithoutPawn(parts[3].to_string()));
+            }
+            Some(EnPassantFieldInfo {
+                ply: ply - 1,
+                skipped,
+                target,
+            })
+        };
+
+        let game_state = GameState {
+            ply,
+            fifty_move_rule_last_event: ply - halfmove_clock,
+            board: BoardState::from_fields(fields, en_passant_field),
+            history: Vec::new(),
+            variant,
+            checks: [0; 2],
+            pockets: [Pocket::default(); 2],
+            promoted: 0,
+        };
+        game_state.validate().map_err(FenError::InvalidPosition)?;
+        Ok(game_state)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for row in (0..8).rev() {
+            let mut empty = 0;
+            for col in 0..8 {
+                match self.board.field(row * 8 + col) {
+                    Some((piece, player)) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(piece_to_char(piece, player));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if row > 0 {
+                placement.push('/');
+            }
+        }
+
+        let turn = match self.turn() {
+            Player::White => "w",
+            Player::Black => "b",
+        };
+
+        let mut castling = String::new();
+        for &player in [Player::White, Player::Black].iter() {
+            let king_pos = match back_row(player)
+                .find(|&pos| self.board.field(pos) == Some((PieceType::InitKing, player)))
+            {
+                Some(king_pos) => king_pos,
+                None => continue,
+            };
+            for &(kingside, side_char) in [(true, 'k'), (false, 'q')].iter() {
+                let rooks = castling_side(player, king_pos, kingside)
+                    .into_iter()
+                    .filter(|&pos| is_rook(self.board.field(pos), player));
+                for (i, rook_pos) in rooks.enumerate() {
+                    if self.board.field(rook_pos) != Some((PieceType::InitRook, player)) {
+                        continue;
+                    }
+                    let c = if i == 0 {
+                        side_char
+                    } else {
+                        (b'a' + (rook_pos % 8) as u8) as char
+                    };
+                    castling.push(match player {
+                        Player::White => c.to_ascii_uppercase(),
+                        Player::Black => c,
+                    });
+                }
+            }
+        }
+        let castling = if castling.is_empty() {
+            "-".to_string()
+        } else {
+            castling
+        };
+
+        let en_passant = match self.en_passant_field() {
+            Some(skipped) => skipped.to_string(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            turn,
+            castling,
+            en_passant,
+            self.ply - self.fifty_move_rule_last_event,
+            self.ply / 2 + 1
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halfmove_clock_beyond_fullmove_number() {
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 10 1").unwrap();
+        assert_eq!(game_state.to_fen(), "4k3/8/8/8/8/8/8/4K2R w K - 10 6");
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/4K2R b K - 3 1").unwrap();
+        assert_eq!(game_state.to_fen(), "4k3/8/8/8/8/8/8/4K2R b K - 3 2");
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/4K2R w - - 4000000 1").unwrap();
+        assert_eq!(game_state.to_fen(), "4k3/8/8/8/8/8/8/4K2R w - - 4000000 2000001");
+        let fen = format!("4k3/8/8/8/8/8/8/4K2R b - - {} 1", usize::MAX);
+        assert!(matches!(GameState::from_fen(&fen), Err(FenError::InvalidHalfmoveClock(_))));
+        let fen = format!("4k3/8/8/8/8/8/8/4K2R w - - 0 {}", usize::MAX);
+        assert!(matches!(GameState::from_fen(&fen), Err(FenError::InvalidFullmoveNumber(_))));
+    }
+}
@@ -1,9 +1,16 @@
 use std::ops;
-use serde::{Serialize, Deserialize, ser::SerializeStruct, de, de::Deserializer, de::Visitor, de::SeqAccess, de::MapAccess};
-use std::fmt;
+use serde::{Serialize, Deserialize, de, de::Deserializer};
 use std::convert::{From, TryInto};
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+use crate::bitboard::{self, bit, bishop_attacks, rook_attacks, Bitboard};
+use crate::bitboard::{KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
+use crate::crazyhouse::Pocket;
+use crate::error::ChaiError;
+use crate::square::{serde_or_invalid, Square};
+use crate::variant::VariantKind;
+use crate::zobrist;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PieceType {
     InitKing,
     King,
@@ -16,7 +23,7 @@ pub enum PieceType {
     Pawn,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Player {
     Black,
     White,
@@ -33,22 +40,61 @@ impl Player {
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BoardState {
-    pub fields: [Option<(PieceType, Player)>; 64],
-    pub en_passant_field: EnPassantFieldInfo,
+    // The fields are kept in sync with the bitboards below, so they can only
+    // be changed through set_field().
+    fields: [Option<(PieceType, Player)>; 64],
+    pub en_passant_field: Option<EnPassantFieldInfo>,
+    pieces: [Bitboard; 9], // indexed by PieceType, both players
+    players: [Bitboard; 2], // indexed by Player
+    placement_hash: u64, // Zobrist keys of all pieces, see zobrist.rs
 }
+// The last double step: the skipped field and the pawn which may be
+// captured en passant in the next ply.
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct EnPassantFieldInfo {
     pub ply: usize,
-    pub skipped: usize,
-    pub target: usize,
+    pub skipped: Square,
+    pub target: Square,
+}
+
+// The front ends always send an en passant field, without a double step
+// the fields are invalid (0xFF).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SerdeEnPassantFieldInfo {
+    ply: usize,
+    #[serde(with = "serde_or_invalid")]
+    skipped: Option<Square>,
+    #[serde(with = "serde_or_invalid")]
+    target: Option<Square>,
+}
+
+impl From<Option<EnPassantFieldInfo>> for SerdeEnPassantFieldInfo {
+    fn from(info: Option<EnPassantFieldInfo>) -> Self {
+        SerdeEnPassantFieldInfo {
+            ply: info.map_or(0, |info| info.ply),
+            skipped: info.map(|info| info.skipped),
+            target: info.map(|info| info.target),
+        }
+    }
+}
+
+impl From<SerdeEnPassantFieldInfo> for Option<EnPassantFieldInfo> {
+    fn from(info: SerdeEnPassantFieldInfo) -> Self {
+        Some(EnPassantFieldInfo {
+            ply: info.ply,
+            skipped: info.skipped?,
+            target: info.target?,
+        })
+    }
 }
 
 // Serde is not able to derive for [T; 64] which is part of BoardState. Thus,
 // for serde, we use a proxy object which proxies [T; 64] through a Vec.
+// The bitboards are not serialized, they are rebuilt from the fields.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SerdeBoardState {
     fields: Vec<Option<(PieceType, Player)>>,
-    en_passant_field: EnPassantFieldInfo,
+    en_passant_field: SerdeEnPassantFieldInfo,
 }
 impl Serialize for BoardState {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -57,7 +103,7 @@ impl Serialize for BoardState {
     {
         let sbs = SerdeBoardState {
             fields: Vec::from(&self.fields[..]),
-            en_passant_field: self.en_passant_field,
+            en_passant_field: self.en_passant_field.into(),
         };
         sbs.serialize(serializer)
     }
@@ -69,17 +115,19 @@ impl<'de> Deserialize<'de> for BoardState {
     {
         let sbs = SerdeBoardState::deserialize(deserializer)?;
         let fields: [_; 64] = (*sbs.fields).try_into().map_err(|_| de::Error::invalid_length(sbs.fields.len(), &"64 fields"))?;
-        Ok(BoardState {
-            fields,
-            en_passant_field: sbs.en_passant_field,
-        })
+        Ok(BoardState::from_fields(fields, sbs.en_passant_field.into()))
+    }
+}
+
+impl Default for BoardState {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl BoardState {
     pub fn new() -> Self {
-        let mut board = [None; 64];
-        let init_row = [
+        BoardState::from_back_rank([
             PieceType::InitRook,
             PieceType::Knight,
             PieceType::Bishop,
@@ -88,44 +136,105 @@ impl BoardState {
             PieceType::Bishop,
             PieceType::Knight,
             PieceType::InitRook,
-        ];
+        ])
+    }
+
+    // A start position with the given pieces on the first and last row (from
+    // the a-file to the h-file) and pawns in front of them.
+    pub fn from_back_rank(init_row: [PieceType; 8]) -> Self {
+        let mut board = [None; 64];
         for i in 0..8 {
-            board[0 * 8 + i] = Some((init_row[i], Player::White));
-            board[1 * 8 + i] = Some((PieceType::InitPawn, Player::White));
+            board[i] = Some((init_row[i], Player::White));
+            board[8 + i] = Some((PieceType::InitPawn, Player::White));
             board[7 * 8 + i] = Some((init_row[i], Player::Black));
             board[6 * 8 + i] = Some((PieceType::InitPawn, Player::Black));
         }
-        let en_passant_field = EnPassantFieldInfo {
-            ply: 0,
-            skipped: 0xFF, // initialized invalid so that condition never true
-            target: 0xFF,
-        };
-        BoardState {
-            fields: board,
+        BoardState::from_fields(board, None)
+    }
+
+    pub fn from_fields(
+        fields: [Option<(PieceType, Player)>; 64],
+        en_passant_field: Option<EnPassantFieldInfo>,
+    ) -> Self {
+        let mut board = BoardState {
+            fields: [None; 64],
             en_passant_field,
+            pieces: [0; 9],
+            players: [0; 2],
+            placement_hash: 0,
+        };
+        for (pos, &field) in fields.iter().enumerate() {
+            board.set_field(pos, field);
         }
+        board
     }
 
-    pub fn get_pieces_with_pos(&self, player: Player) -> Vec<(PieceType, usize)> {
-        let mut pieces = Vec::with_capacity(16);
-        for pos in 0..64 {
-            if let Some((piece, curr_player)) = self.fields[pos] {
-                if curr_player == player {
-                    pieces.push((piece, pos));
-                }
-            }
+    pub fn fields(&self) -> &[Option<(PieceType, Player)>; 64] {
+        &self.fields
+    }
+
+    pub fn field(&self, pos: usize) -> Option<(PieceType, Player)> {
+        self.fields[pos]
+    }
+
+    pub fn set_field(&mut self, pos: usize, field: Option<(PieceType, Player)>) {
+        if let Some((piece, player)) = self.fields[pos] {
+            self.pieces[piece as usize] &= !bit(pos);
+            self.players[player as usize] &= !bit(pos);
+            self.placement_hash ^= zobrist::piece_key(piece, player, pos);
         }
-        pieces
+        if let Some((piece, player)) = field {
+            self.pieces[piece as usize] |= bit(pos);
+            self.players[player as usize] |= bit(pos);
+            self.placement_hash ^= zobrist::piece_key(piece, player, pos);
+        }
+        self.fields[pos] = field;
     }
 
-    pub fn get_pieces(&self) -> Vec<(PieceType, Player)> {
-        let mut pieces = Vec::with_capacity(32);
-        for pos in 0..64 {
-            if let Some(pp) = self.fields[pos] {
-                pieces.push(pp);
+    pub fn occupied(&self) -> Bitboard {
+        self.players[0] | self.players[1]
+    }
+
+    pub fn player_pieces(&self, player: Player) -> Bitboard {
+        self.players[player as usize]
+    }
+
+    // The Init* variants are the same kind of piece, so they are included.
+    pub fn pieces(&self, piece: PieceType, player: Player) -> Bitboard {
+        let pieces = match piece {
+            PieceType::InitKing | PieceType::King => {
+                self.pieces[PieceType::InitKing as usize] | self.pieces[PieceType::King as usize]
             }
-        }
-        pieces
+            PieceType::InitRook | PieceType::Rook => {
+                self.pieces[PieceType::InitRook as usize] | self.pieces[PieceType::Rook as usize]
+            }
+            PieceType::InitPawn | PieceType::Pawn => {
+                self.pieces[PieceType::InitPawn as usize] | self.pieces[PieceType::Pawn as usize]
+            }
+            piece => self.pieces[piece as usize],
+        };
+        pieces & self.players[player as usize]
+    }
+
+    // Unlike pieces(), only the given variant, e.g. only unmoved rooks.
+    pub fn pieces_of_type(&self, piece: PieceType, player: Player) -> Bitboard {
+        self.pieces[piece as usize] & self.players[player as usize]
+    }
+
+    pub fn placement_hash(&self) -> u64 {
+        self.placement_hash
+    }
+
+    pub fn get_pieces_with_pos(&self, player: Player) -> Vec<(PieceType, usize)> {
+        bitboard::fields(self.player_pieces(player))
+            .map(|pos| (self.fields[pos].expect("Bitboards out of sync.").0, pos))
+            .collect()
+    }
+
+    pub fn get_pieces(&self) -> Vec<(PieceType, Player)> {
+        bitboard::fields(self.occupied())
+            .map(|pos| self.fields[pos].expect("Bitboards out of sync."))
+            .collect()
     }
 
     // far = pieces which can move far distances ... plus king because it behaves
@@ -160,86 +269,226 @@ impl BoardState {
         positions
     }
 
-    pub fn find_king(&self, player: Player) -> usize {
-        for pos in 0..64 {
-            if self.fields[pos] == Some((PieceType::King, player))
-                || self.fields[pos] == Some((PieceType::InitKing, player))
-            {
-                return pos;
+    // The fields the given piece on pos attacks, regardless of what is there.
+    pub fn attacks(&self, piece: PieceType, pos: usize, player: Player) -> Bitboard {
+        match piece {
+            PieceType::InitKing | PieceType::King => KING_ATTACKS[pos],
+            PieceType::Queen => {
+                rook_attacks(pos, self.occupied()) | bishop_attacks(pos, self.occupied())
             }
+            PieceType::InitRook | PieceType::Rook => rook_attacks(pos, self.occupied()),
+            PieceType::Bishop => bishop_attacks(pos, self.occupied()),
+            PieceType::Knight => KNIGHT_ATTACKS[pos],
+            PieceType::InitPawn | PieceType::Pawn => PAWN_ATTACKS[player as usize][pos],
+        }
+    }
+
+    // Neither player can checkmate anymore: K vs K, K+minor vs K and kings
+    // with any number of bishops which all stand on fields of one color.
+    pub fn insufficient_material(&self) -> bool {
+        let pawns_rooks_queens = self.pieces[PieceType::InitPawn as usize]
+            | self.pieces[PieceType::Pawn as usize]
+            | self.pieces[PieceType::InitRook as usize]
+            | self.pieces[PieceType::Rook as usize]
+            | self.pieces[PieceType::Queen as usize];
+        if pawns_rooks_queens != 0 {
+            return false;
         }
-        panic!("Where is your King?");
+        let knights = self.pieces[PieceType::Knight as usize];
+        let bishops = self.pieces[PieceType::Bishop as usize];
+        if (knights | bishops).count_ones() <= 1 {
+            return true;
+        }
+        let dark_bishops = bishops & bitboard::DARK_FIELDS;
+        knights == 0 && (dark_bishops == 0 || dark_bishops == bishops)
+    }
+
+    // The pawn which is captured by an en passant move.
+    pub fn en_passant_target(&self) -> usize {
+        self.en_passant_field
+            .expect("There was no double step to capture en passant.")
+            .target
+            .index()
+    }
+
+    pub fn find_king(&self, player: Player) -> Result<usize, ChaiError> {
+        let kings = self.pieces(PieceType::King, player);
+        if kings == 0 {
+            return Err(ChaiError::NoKing(player));
+        }
+        Ok(kings.trailing_zeros() as usize)
+    }
+
+    // The pieces of the attacker which attack pos.
+    pub fn attackers(&self, pos: usize, attacker: Player) -> Bitboard {
+        self.attackers_through(pos, attacker, self.occupied())
     }
 
+    // Like attackers(), but sliders are only blocked by the given occupied
+    // fields. Pieces which are not in occupied are still returned.
+    pub fn attackers_through(&self, pos: usize, attacker: Player, occupied: Bitboard) -> Bitboard {
+        let queens = self.pieces(PieceType::Queen, attacker);
+        // For pawns, we need the capture direction of the other player's
+        // pawns because that's where attacking pawns (looking from pos) are
+        // located. It's a little bit counter-intuitive.
+        KNIGHT_ATTACKS[pos] & self.pieces(PieceType::Knight, attacker)
+            | KING_ATTACKS[pos] & self.pieces(PieceType::King, attacker)
+            | PAWN_ATTACKS[attacker.opponent() as usize][pos] & self.pieces(PieceType::Pawn, attacker)
+            | rook_attacks(pos, occupied) & (self.pieces(PieceType::Rook, attacker) | queens)
+            | bishop_attacks(pos, occupied) & (self.pieces(PieceType::Bishop, attacker) | queens)
+    }
+
+    // Whether the opponent of player attacks pos.
     fn field_under_attack(&self, pos: usize, player: Player) -> bool {
-        for (other_pos, distance) in
-            self.get_far_moves(pos, &DIRECTIONS[STRAIGHT], 7, false, true, player)
-        {
-            match self.fields[other_pos].expect("Only requested occupied fields.") {
-                (PieceType::InitKing, p) | (PieceType::King, p) if distance == 1 => {
-                    if p != player {
-                        return true;
-                    }
-                }
-                (PieceType::Queen, p) | (PieceType::InitRook, p) | (PieceType::Rook, p) => {
-                    if p != player {
-                        return true;
-                    }
-                }
-                _ => {}
-            }
+        self.attackers(pos, player.opponent()) != 0
+    }
+
+    // A player without a king (only possible in variants) is never in check.
+    pub fn king_in_check(&self, player: Player) -> bool {
+        match self.find_king(player) {
+            Ok(king_pos) => self.field_under_attack(king_pos, player),
+            Err(_) => false,
         }
-        for (other_pos, distance) in
-            self.get_far_moves(pos, &DIRECTIONS[DIAGONAL], 7, false, true, player)
-        {
-            match self.fields[other_pos].expect("Only requested occupied fields.") {
-                (PieceType::InitKing, p) | (PieceType::King, p) if distance == 1 => {
-                    if p != player {
-                        return true;
-                    }
-                }
-                (PieceType::Queen, p) | (PieceType::Bishop, p) => {
-                    if p != player {
-                        return true;
-                    }
-                }
-                _ => {}
-            }
+    }
+
+    // Moves the pieces for the given move which is played at the given ply.
+    // Returns what is needed to take back the move, except for the move
+    // counters which are not part of the board.
+    pub(crate) fn apply_move(&mut self, mv: Move, ply: usize) -> UndoInfo {
+        let en_passant_field = self.en_passant_field;
+        if let Some(piece) = mv.drop {
+            return self.apply_drop(mv, piece, ply, en_passant_field);
         }
-        for (other_pos, _) in self.get_far_moves(pos, &DIRECTIONS[KNIGHT], 1, false, true, player) {
-            match self.fields[other_pos].expect("Only requested occupied fields.") {
-                (PieceType::Knight, p) => {
-                    if p != player {
-                        return true;
-                    }
-                }
-                _ => {}
-            }
+        let (piece, player) = self.fields[mv.from].expect("There is no piece to move.");
+        self.set_field(mv.from, None);
+        let new_piece = match (mv.promotion, piece) {
+            (Some(promoted), _) => promoted,
+            (None, PieceType::InitKing) => PieceType::King,
+            (None, PieceType::InitRook) => PieceType::Rook,
+            (None, PieceType::InitPawn) => PieceType::Pawn,
+            (None, piece) => piece,
+        };
+        if mv.castling {
+            return self.apply_castling(mv, piece, player, en_passant_field);
         }
-        // For pawns, we need the movement direction of our own pawns because that's
-        // where attacking pawns (looking from our position) are located. It's
-        // a little bit counter-intuitive.
-        let (_, _, capture_moves) = get_pawn_moves(player);
-        for (other_pos, _) in self.get_far_moves(pos, capture_moves, 1, false, true, player) {
-            match self.fields[other_pos].expect("Only requested occupied fields.") {
-                (PieceType::InitPawn, p) | (PieceType::Pawn, p) => {
-                    if p != player {
-                        return true;
-                    }
+        let captured = if mv.en_passant {
+            let target = self.en_passant_target();
+            let captured = self.fields[target];
+            self.set_field(target, None);
+            captured
+        } else {
+            self.fields[mv.to]
+        };
+        self.set_field(mv.to, Some((new_piece, player)));
+        if piece == PieceType::InitPawn && (mv.from as isize - mv.to as isize).abs() == 16 {
+            // Handling en passant movements. Here: remember that
+            // a double step occured.
+            self.en_passant_field = Some(EnPassantFieldInfo {
+                ply,
+                skipped: Square::at((mv.from + mv.to) / 2),
+                target: Square::at(mv.to),
+            });
+        }
+        UndoInfo {
+            piece,
+            captured,
+            castling_rook: None,
+            en_passant_field,
+            ..UndoInfo::new(piece, en_passant_field)
+        }
+    }
+
+    // Crazyhouse: A dropped pawn on its initial row may do a double step
+    // like any other unmoved pawn.
+    fn apply_drop(
+        &mut self,
+        mv: Move,
+        piece: PieceType,
+        ply: usize,
+        en_passant_field: Option<EnPassantFieldInfo>,
+    ) -> UndoInfo {
+        let player = if ply.is_multiple_of(2) {
+            Player::White
+        } else {
+            Player::Black
+        };
+        let initial_row = match player {
+            Player::White => 1,
+            Player::Black => 6,
+        };
+        let piece = if piece == PieceType::Pawn && mv.to / 8 == initial_row {
+            PieceType::InitPawn
+        } else {
+            piece
+        };
+        self.set_field(mv.to, Some((piece, player)));
+        UndoInfo::new(piece, en_passant_field)
+    }
+
+    // Atomic chess: The capturing piece explodes on the field of the capture
+    // together with all pieces next to it, except for pawns.
+    pub(crate) fn explode(&mut self, center: usize, undo: &mut UndoInfo) {
+        self.set_field(center, None);
+        for (i, pos) in bitboard::fields(KING_ATTACKS[center]).enumerate() {
+            match self.fields[pos] {
+                Some((PieceType::InitPawn, _)) | Some((PieceType::Pawn, _)) | None => {}
+                Some(field) => {
+                    undo.exploded[i] = Some((pos, field));
+                    self.set_field(pos, None);
                 }
-                _ => {}
             }
         }
-        false
     }
 
-    pub fn king_in_check(&self, player: Player) -> bool {
-        let king_pos = self.find_king(player);
-        self.field_under_attack(king_pos, player)
+    // The king has already been removed from mv.from. In Chess960, the king
+    // and the rook may end up on each other's or their own fields, so both
+    // are removed before they are placed again.
+    fn apply_castling(
+        &mut self,
+        mv: Move,
+        piece: PieceType,
+        player: Player,
+        en_passant_field: Option<EnPassantFieldInfo>,
+    ) -> UndoInfo {
+        let kingside = mv.is_kingside_castling();
+        let rook_from = self
+            .castling_rook(mv.from, player, kingside)
+            .expect("There is no rook to castle with.");
+        let rook_to = castling_rook_destination(mv.to, kingside);
+        self.set_field(rook_from, None);
+        self.set_field(mv.to, Some((PieceType::King, player)));
+        self.set_field(rook_to, Some((PieceType::Rook, player)));
+        UndoInfo {
+            piece,
+            captured: None,
+            castling_rook: Some((rook_from, rook_to)),
+            en_passant_field,
+            ..UndoInfo::new(piece, en_passant_field)
+        }
+    }
+
+    // The rook a king on king_pos castles with: the nearest unmoved rook
+    // towards the board edge on the given side.
+    pub fn castling_rook(&self, king_pos: usize, player: Player, kingside: bool) -> Option<usize> {
+        let step = if kingside { 1 } else { -1 };
+        get_steps(king_pos, (0, step), 7)
+            .into_iter()
+            .map(|(rook_pos, _)| rook_pos)
+            .find(|&rook_pos| self.fields[rook_pos] == Some((PieceType::InitRook, player)))
     }
 }
 
-type Direction = (isize, isize);
+// Chess960 rules: Wherever king and rook start, they end up on the same
+// fields as in standard chess, i.e. on the g- and f-file or c- and d-file.
+pub fn castling_king_destination(king_pos: usize, kingside: bool) -> usize {
+    king_pos / 8 * 8 + if kingside { 6 } else { 2 }
+}
+
+fn castling_rook_destination(king_to: usize, kingside: bool) -> usize {
+    king_to / 8 * 8 + if kingside { 5 } else { 3 }
+}
+
+pub type Direction = (isize, isize);
 
 const DIRECTIONS: [Direction; 16] = [
     (1, 0),
@@ -264,19 +513,25 @@ const WHITE_PAWN_MOVE: ops::Range<usize> = 0..1;
 const BLACK_PAWN_MOVE: ops::Range<usize> = 1..2;
 const WHITE_PAWN_CAPTURE: ops::Range<usize> = 4..6;
 const BLACK_PAWN_CAPTURE: ops::Range<usize> = 6..8;
-const STRAIGHT: ops::Range<usize> = 0..4;
-const DIAGONAL: ops::Range<usize> = 4..8;
-const STRAIGHT_AND_DIAGONAL: ops::Range<usize> = 0..8;
-const KNIGHT: ops::Range<usize> = 8..16;
 
 pub fn pos_from_rowcol(row: isize, col: isize) -> Option<usize> {
-    if row >= 0 && row < 8 && col >= 0 && col < 8 {
+    if (0..8).contains(&row) && (0..8).contains(&col) {
         Some(row as usize * 8 + col as usize)
     } else {
         None
     }
 }
 
+// Algebraic coordinates, e.g. 0 <-> "a1", 12 <-> "e2", 63 <-> "h8", for
+// the places where fields are still plain indices.
+pub fn pos_to_algebraic(pos: usize) -> String {
+    Square::at(pos).to_string()
+}
+
+pub fn pos_from_algebraic(s: &str) -> Option<usize> {
+    s.parse::<Square>().ok().map(Square::index)
+}
+
 pub fn get_steps(pos: usize, direction: Direction, steps: usize) -> Vec<(usize, usize)> {
     let row = (pos / 8) as isize;
     let col = (pos % 8) as isize;
@@ -291,6 +546,12 @@ pub fn get_steps(pos: usize, direction: Direction, steps: usize) -> Vec<(usize,
     positions
 }
 
+// The fields on one row from a to b, both included.
+fn fields_between(a: usize, b: usize) -> Bitboard {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+    (low..=high).fold(0, |fields, pos| fields | bit(pos))
+}
+
 fn get_pawn_moves(player: Player) -> (usize, &'static [Direction], &'static [Direction]) {
     match player {
         Player::White => (
@@ -306,11 +567,110 @@ fn get_pawn_moves(player: Player) -> (usize, &'static [Direction], &'static [Dir
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Move {
+    pub from: usize,
+    pub to: usize,
+    pub promotion: Option<PieceType>,
+    pub capture: bool,
+    pub en_passant: bool,
+    pub castling: bool, // from and to describe the king's movement
+    // Crazyhouse: the piece is taken from the pocket and put on to (from is
+    // the same field).
+    #[serde(default)]
+    pub drop: Option<PieceType>,
+}
+
+impl Move {
+    pub fn new(from: usize, to: usize, capture: bool) -> Self {
+        Move {
+            from,
+            to,
+            promotion: None,
+            capture,
+            en_passant: false,
+            castling: false,
+            drop: None,
+        }
+    }
+
+    pub fn new_drop(piece: PieceType, to: usize) -> Self {
+        Move {
+            drop: Some(piece),
+            ..Move::new(to, to, false)
+        }
+    }
+
+    // Castling moves are stored as the king's move. In Chess960 the king may
+    // even move towards the other side or stay where it is, so the side is
+    // told by its destination.
+    pub fn is_kingside_castling(&self) -> bool {
+        self.castling && self.to % 8 == 6
+    }
+}
+
+pub const PROMOTION_PIECES: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+// Everything which is needed to take back a move but which can not be
+// derived from the move itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UndoInfo {
+    piece: PieceType,
+    captured: Option<(PieceType, Player)>,
+    castling_rook: Option<(usize, usize)>,
+    en_passant_field: Option<EnPassantFieldInfo>,
+    fifty_move_rule_last_event: usize,
+    checks: [usize; 2],
+    exploded: [Option<(usize, (PieceType, Player))>; 8], // Atomic chess
+    pockets: [Pocket; 2],                                 // Crazyhouse
+    promoted: Bitboard,
+}
+
+impl UndoInfo {
+    // The board does not know about the counters, checks and pockets, they
+    // are filled in by the GameState.
+    fn new(piece: PieceType, en_passant_field: Option<EnPassantFieldInfo>) -> Self {
+        UndoInfo {
+            piece,
+            captured: None,
+            castling_rook: None,
+            en_passant_field,
+            fifty_move_rule_last_event: 0,
+            checks: [0; 2],
+            exploded: [None; 8],
+            pockets: [Pocket::default(); 2],
+            promoted: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GameState {
     pub ply: usize,
     pub fifty_move_rule_last_event: usize,
     pub board: BoardState,
+    // Position keys of all previous positions, oldest first. Only the ones
+    // since fifty_move_rule_last_event can ever be repeated because pawn
+    // moves and captures are irreversible.
+    #[serde(default, with = "zobrist::serde_keys")]
+    pub history: Vec<u64>,
+    #[serde(default)]
+    pub variant: VariantKind,
+    // The number of checks given by each player (indexed by Player), only
+    // counted if the variant needs them, see Variant::counts_checks().
+    #[serde(default)]
+    pub checks: [usize; 2],
+    // Crazyhouse: the captured pieces each player may drop (indexed by
+    // Player) and the fields of pieces which were pawns before promotion.
+    #[serde(default)]
+    pub pockets: [Pocket; 2],
+    #[serde(default, with = "bitboard::serde_fields")]
+    pub promoted: Bitboard,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -318,269 +678,425 @@ pub enum FinishedState {
     Ongoing, // the player whose turn it is is guaranteed to have at least one legal move!
     Checkmate,
     Stalemate,
-    ThreefoldRepetition, // TODO not implemented yet
+    ThreefoldRepetition, // the player to move has legal moves but may claim a draw
+    FivefoldRepetition,
     FiftyMoveDraw,
+    InsufficientMaterial,
+    KingOfTheHill, // the player who moved last reached the center with the king
+    ThreeChecks,   // the player who moved last gave the third check
+    KingExploded,  // the player who moved last blew up the other king (Atomic chess)
+    OutOfMoves,    // the player to move has no pieces or moves left and wins (Antichess)
 }
 
 impl GameState {
+    pub fn new(variant: VariantKind) -> GameState {
+        GameState {
+            ply: 0,
+            fifty_move_rule_last_event: 0,
+            board: variant.rules().start_position(),
+            history: Vec::new(),
+            variant,
+            checks: [0; 2],
+            pockets: [Pocket::default(); 2],
+            promoted: 0,
+        }
+    }
+
     pub fn turn(&self) -> Player {
-        if self.ply % 2 == 0 {
+        if self.ply.is_multiple_of(2) {
             Player::White
         } else {
             Player::Black
         }
     }
 
-    fn new_state_from_to(&self, piece: PieceType, from: usize, to: usize) -> (usize, GameState) {
-        let mut new_state = *self;
-        new_state.ply = self.ply + 1;
-        new_state.board.fields[from] = None;
-        let old = new_state.board.fields[to].replace((piece, self.turn()));
-        if old != None || piece == PieceType::Pawn {
-            new_state.fifty_move_rule_last_event = new_state.ply;
+    // The field skipped by a double step in the last ply, if any.
+    pub fn en_passant_field(&self) -> Option<Square> {
+        self.board
+            .en_passant_field
+            .filter(|en_passant| self.ply == en_passant.ply + 1)
+            .map(|en_passant| en_passant.skipped)
+    }
+
+    // Zobrist hash of the position (pieces, castling rights, en passant and
+    // the player to move). The piece placement part is maintained
+    // incrementally by the board, see position_key_from_scratch() for the
+    // full recomputation.
+    pub fn position_key(&self) -> u64 {
+        self.board.placement_hash() ^ self.non_placement_hash()
+    }
+
+    // How often the current position has occured, including the current one.
+    pub fn repetition_count(&self) -> usize {
+        let key = self.position_key();
+        let reversible_plies = self.ply - self.fifty_move_rule_last_event;
+        1 + self
+            .history
+            .iter()
+            .rev()
+            .take(reversible_plies)
+            .skip(1)
+            .step_by(2)
+            .filter(|&&old_key| old_key == key)
+            .count()
+    }
+
+    pub fn threefold_repetition(&self) -> bool {
+        self.repetition_count() >= 3
+    }
+
+    pub fn fivefold_repetition(&self) -> bool {
+        self.repetition_count() >= 5
+    }
+
+    pub fn make_move(&mut self, mv: Move) -> UndoInfo {
+        self.history.push(self.position_key());
+        let rules = self.variant.rules();
+        let mut undo = rules.apply_move(&mut self.board, mv, self.ply);
+        undo.fifty_move_rule_last_event = self.fifty_move_rule_last_event;
+        undo.checks = self.checks;
+        undo.pockets = self.pockets;
+        undo.promoted = self.promoted;
+        let player = self.turn();
+        if rules.uses_pockets() {
+            self.update_pockets(mv, undo.captured);
+        }
+        self.ply += 1;
+        if undo.captured.is_some()
+            || undo.piece == PieceType::InitPawn
+            || undo.piece == PieceType::Pawn
+        {
+            self.fifty_move_rule_last_event = self.ply;
+        }
+        if rules.counts_checks() && self.in_check() {
+            self.checks[player as usize] += 1;
         }
-        (to, new_state)
+        debug_assert!(self.verify_position_key());
+        undo
     }
 
-    fn store_en_passant_info(&self, state: &mut GameState, pos: usize, new_pos: usize) {
-        // Handling en passant movements. Here: remember that
-        // a double step occured.
-        state.board.en_passant_field = EnPassantFieldInfo {
-            ply: self.ply,
-            skipped: (pos + new_pos) / 2,
-            target: new_pos,
+    // For moves which do not come from the move generator, e.g. user input.
+    pub fn try_make_move(&mut self, mv: Move) -> Result<UndoInfo, ChaiError> {
+        if !self.get_legal_moves().contains(&mv) {
+            return Err(ChaiError::IllegalMove(mv));
         }
+        Ok(self.make_move(mv))
     }
 
-    fn generate_pawn_promotions(
-        &self,
-        pos_and_state: (usize, GameState),
-    ) -> [(usize, GameState); 4] {
-        let new_pos = pos_and_state.0;
-        let mut states = [pos_and_state; 4];
-        states[0].1.board.fields[new_pos] = Some((PieceType::Queen, self.turn()));
-        states[1].1.board.fields[new_pos] = Some((PieceType::Rook, self.turn()));
-        states[2].1.board.fields[new_pos] = Some((PieceType::Bishop, self.turn()));
-        states[3].1.board.fields[new_pos] = Some((PieceType::Knight, self.turn()));
-        states
+    pub fn unmake_move(&mut self, mv: Move, undo: UndoInfo) {
+        let player = self.turn().opponent();
+        self.ply -= 1;
+        self.history.pop();
+        self.fifty_move_rule_last_event = undo.fifty_move_rule_last_event;
+        self.checks = undo.checks;
+        self.pockets = undo.pockets;
+        self.promoted = undo.promoted;
+        self.board.en_passant_field = undo.en_passant_field;
+        self.board.set_field(mv.to, None);
+        if let Some((rook_from, rook_to)) = undo.castling_rook {
+            self.board.set_field(rook_to, None);
+            self.board.set_field(rook_from, Some((PieceType::InitRook, player)));
+        }
+        if mv.drop.is_none() {
+            self.board.set_field(mv.from, Some((undo.piece, player)));
+        }
+        if let Some(captured) = undo.captured {
+            let captured_pos = if mv.en_passant {
+                self.board.en_passant_target()
+            } else {
+                mv.to
+            };
+            self.board.set_field(captured_pos, Some(captured));
+        }
+        for &(pos, field) in undo.exploded.iter().flatten() {
+            self.board.set_field(pos, Some(field));
+        }
+        debug_assert!(self.verify_position_key());
+    }
+
+    // Convenience for callers which want to keep the old state, e.g. UIs
+    // which show the outcome of a move.
+    pub fn after_move(&self, mv: Move) -> GameState {
+        let mut new_state = self.clone();
+        new_state.make_move(mv);
+        new_state
     }
 
+    // The rules are defined by the variant, see variant.rs.
     pub fn get_finished_state(&self) -> FinishedState {
-        if self.fifty_move_rule_draw() {
-            FinishedState::FiftyMoveDraw
-        } else if self.get_legal_moves().len() == 0 {
-            if self.board.king_in_check(self.turn()) {
-                FinishedState::Checkmate
-            } else {
-                FinishedState::Stalemate
-            }
+        let rules = self.variant.rules();
+        if let Some(finished) = rules.game_end(self) {
+            finished
+        } else if self.get_legal_moves().is_empty() {
+            rules.no_legal_moves(self)
+        } else if self.threefold_repetition() {
+            FinishedState::ThreefoldRepetition
         } else {
             FinishedState::Ongoing
         }
     }
 
-    /*
-    pub fn get_legal_moves(&self) -> Vec<GameState> {
-        //self.get_pseudo_legal_moves().drain_filter(...) // currently nightly only
-        self.get_pseudo_legal_moves()
-            .drain(..)
-            .filter(|&new_state| !new_state.board.king_in_check(self.turn()))
-            .collect()
+    pub fn get_legal_moves(&self) -> Vec<Move> {
+        self.legal_moves().collect()
     }
-    */
 
-    pub fn get_legal_moves(&self) -> Vec<GameState> {
-        let mut new_states = Vec::new();
-        for (piece, pos) in self.board.get_pieces_with_pos(self.turn()) {
-            new_states.extend(
-                self.get_legal_moves_for_single_piece(piece, pos)
-                    .into_iter()
-                    .map(|(_new_pos, new_state)| new_state),
-            );
-        }
-        new_states
+    // The legal moves in the order of MovePicker, generated on demand.
+    pub fn legal_moves(&self) -> impl Iterator<Item = Move> + '_ {
+        let mut picker = MovePicker::new();
+        std::iter::from_fn(move || picker.next_move(self))
     }
 
     /*
-    pub fn get_pseudo_legal_moves(&self) -> Vec<GameState> {
-        let mut new_states = Vec::new();
+    pub fn get_pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
         for (piece, pos) in self.board.get_pieces_with_pos(self.turn()) {
-            new_states.extend(self.get_pseudo_legal_moves_for_single_piece(piece, pos));
+            moves.extend(self.get_pseudo_legal_moves_for_single_piece(piece, pos));
         }
-        new_states
+        moves
     }
     */
 
-    pub fn get_legal_moves_for_single_piece(
-        &self,
-        piece: PieceType,
-        pos: usize,
-    ) -> Vec<(usize, GameState)> {
-        //self.get_pseudo_legal_moves_for_single_piece().drain_filter(...) // currently nightly only
-        self.get_pseudo_legal_moves_for_single_piece(piece, pos)
-            .drain(..)
-            .filter(|&(_new_pos, new_state)| !new_state.board.king_in_check(self.turn()))
-            .collect()
+    // Variants may restrict the moves depending on the other pieces' moves,
+    // so all legal moves are generated.
+    pub fn get_legal_moves_for_single_piece(&self, pos: usize) -> Vec<Move> {
+        let mut moves = self.get_legal_moves();
+        moves.retain(|mv| mv.from == pos && mv.drop.is_none());
+        moves
     }
 
-    pub fn get_pseudo_legal_moves_for_single_piece(
-        &self,
-        piece: PieceType,
-        pos: usize,
-    ) -> Vec<(usize, GameState)> {
-        let mut new_states = Vec::new();
+    // Whether the player to move is in check, the rules are up to the
+    // variant.
+    pub fn in_check(&self) -> bool {
+        self.variant.rules().in_check(&self.board, self.turn())
+    }
+
+    pub fn king_in_check_after(&self, mv: Move) -> bool {
+        // Only the board is needed to find out if the king is in check,
+        // so we do not need to copy the whole GameState.
+        let rules = self.variant.rules();
+        let mut board = self.board;
+        rules.apply_move(&mut board, mv, self.ply);
+        rules.in_check(&board, self.turn())
+    }
+
+    fn push_pawn_move(&self, moves: &mut Vec<Move>, mv: Move) {
+        let (final_row, _, _) = get_pawn_moves(self.turn());
+        if mv.to / 8 == final_row {
+            for &piece in self.variant.rules().promotion_pieces() {
+                moves.push(Move {
+                    promotion: Some(piece),
+                    ..mv
+                });
+            }
+        } else {
+            moves.push(mv);
+        }
+    }
+
+    pub fn get_pseudo_legal_moves_for_single_piece(&self, piece: PieceType, pos: usize) -> Vec<Move> {
+        let mut moves = Vec::new();
+        self.push_pseudo_legal_captures(&mut moves, piece, pos);
+        self.push_pseudo_legal_non_captures(&mut moves, piece, pos);
+        moves
+    }
+
+    // Captures including en passant and promotions which capture.
+    fn push_pseudo_legal_captures(&self, moves: &mut Vec<Move>, piece: PieceType, pos: usize) {
+        let turn = self.turn();
+        let opponent = self.board.player_pieces(turn.opponent());
+        let attacks = self.board.attacks(piece, pos, turn);
         match piece {
-            PieceType::InitKing | PieceType::King => {
-                for (new_pos, _) in self.board.get_far_moves(
-                    pos,
-                    &DIRECTIONS[STRAIGHT_AND_DIAGONAL],
-                    1,
-                    true,
-                    true,
-                    self.turn(),
-                ) {
-                    new_states.push(self.new_state_from_to(PieceType::King, pos, new_pos));
+            PieceType::InitPawn | PieceType::Pawn => {
+                for new_pos in bitboard::fields(attacks & opponent) {
+                    self.push_pawn_move(moves, Move::new(pos, new_pos, true));
                 }
-                if PieceType::InitKing == piece && !self.board.field_under_attack(pos, self.turn())
-                {
-                    let castling_options: [(isize, isize); 2] = [(-1, 4), (1, 3)];
-                    for &(step, rook_distance) in castling_options.iter() {
-                        let castling_pos = |distance| ((pos as isize) + step * distance) as usize;
-                        if self.board.fields[castling_pos(rook_distance)]
-                            != Some((PieceType::InitRook, self.turn()))
-                        {
-                            continue;
-                        }
-                        let occupied_field = (1..rook_distance)
-                            .find(|&distance| self.board.fields[castling_pos(distance)] != None);
-                        if let Some(_) = occupied_field {
-                            continue;
-                        }
-                        if self.board.field_under_attack(castling_pos(1), self.turn())
-                            || self.board.field_under_attack(castling_pos(2), self.turn())
-                        {
-                            continue;
-                        }
-                        let mut new_pos_and_state =
-                            self.new_state_from_to(PieceType::King, pos, castling_pos(2));
-                        new_pos_and_state.1.board.fields[castling_pos(rook_distance)] = None;
-                        new_pos_and_state.1.board.fields[castling_pos(1)] =
-                            Some((PieceType::Rook, self.turn()));
-                        new_states.push(new_pos_and_state);
+                // No promotions while capturing en-passant possible
+                if let Some(skipped) = self.en_passant_field() {
+                    if attacks & skipped.bit() != 0 {
+                        moves.push(Move {
+                            en_passant: true,
+                            ..Move::new(pos, skipped.index(), true)
+                        });
                     }
                 }
             }
-            PieceType::Queen => {
-                for (new_pos, _) in self.board.get_far_moves(
-                    pos,
-                    &DIRECTIONS[STRAIGHT_AND_DIAGONAL],
-                    7,
-                    true,
-                    true,
-                    self.turn(),
-                ) {
-                    new_states.push(self.new_state_from_to(PieceType::Queen, pos, new_pos));
+            _ => {
+                for new_pos in bitboard::fields(attacks & opponent) {
+                    moves.push(Move::new(pos, new_pos, true));
                 }
             }
-            PieceType::InitRook | PieceType::Rook => {
+        }
+    }
+
+    // Pawn pushes including promotions, the other pieces' moves to empty
+    // fields and castling.
+    fn push_pseudo_legal_non_captures(&self, moves: &mut Vec<Move>, piece: PieceType, pos: usize) {
+        let turn = self.turn();
+        match piece {
+            PieceType::InitPawn | PieceType::Pawn => {
+                let (_, move_moves, _) = get_pawn_moves(turn);
+                let max_steps = if piece == PieceType::InitPawn { 2 } else { 1 };
                 for (new_pos, _) in
                     self.board
-                        .get_far_moves(pos, &DIRECTIONS[STRAIGHT], 7, true, true, self.turn())
+                        .get_far_moves(pos, move_moves, max_steps, true, false, turn)
                 {
-                    new_states.push(self.new_state_from_to(PieceType::Rook, pos, new_pos));
+                    self.push_pawn_move(moves, Move::new(pos, new_pos, false));
                 }
             }
-            PieceType::Bishop => {
-                for (new_pos, _) in
-                    self.board
-                        .get_far_moves(pos, &DIRECTIONS[DIAGONAL], 7, true, true, self.turn())
-                {
-                    new_states.push(self.new_state_from_to(PieceType::Bishop, pos, new_pos));
+            _ => {
+                let attacks = self.board.attacks(piece, pos, turn);
+                for new_pos in bitboard::fields(attacks & !self.board.occupied()) {
+                    moves.push(Move::new(pos, new_pos, false));
                 }
             }
-            PieceType::Knight => {
-                for (new_pos, _) in
-                    self.board
-                        .get_far_moves(pos, &DIRECTIONS[KNIGHT], 1, true, true, self.turn())
+        }
+        if PieceType::InitKing == piece && !self.board.field_under_attack(pos, turn) {
+            for &kingside in [false, true].iter() {
+                let rook_pos = match self.board.castling_rook(pos, turn, kingside) {
+                    Some(rook_pos) => rook_pos,
+                    None => continue,
+                };
+                let king_to = castling_king_destination(pos, kingside);
+                let rook_to = castling_rook_destination(king_to, kingside);
+                // Apart from the king and the rook themselves, all fields
+                // both of them cross have to be empty and the king must not
+                // cross attacked fields. Whether the king's destination is
+                // attacked is checked like for every other move.
+                let others = self.board.occupied() & !bit(pos) & !bit(rook_pos);
+                if others & fields_between(pos, king_to) != 0
+                    || others & fields_between(rook_pos, rook_to) != 0
                 {
-                    new_states.push(self.new_state_from_to(PieceType::Knight, pos, new_pos));
+                    continue;
                 }
-            }
-            PieceType::InitPawn => {
-                let (_, move_moves, capture_moves) = get_pawn_moves(self.turn());
-                for (i, (new_pos, _)) in self
-                    .board
-                    .get_far_moves(pos, move_moves, 2, true, false, self.turn())
-                    .iter()
-                    .enumerate()
-                {
-                    let mut new_pos_and_state =
-                        self.new_state_from_to(PieceType::Pawn, pos, *new_pos);
-                    if i == 1 {
-                        self.store_en_passant_info(&mut new_pos_and_state.1, pos, *new_pos);
-                    }
-                    new_states.push(new_pos_and_state);
+                let king_path_attacked = bitboard::fields(fields_between(pos, king_to))
+                    .any(|field| field != pos && self.board.field_under_attack(field, turn));
+                if king_path_attacked {
+                    continue;
                 }
-                for (new_pos, _) in
-                    self.board
-                        .get_far_moves(pos, capture_moves, 1, false, true, self.turn())
-                {
-                    new_states.push(self.new_state_from_to(PieceType::Pawn, pos, new_pos));
+                moves.push(Move {
+                    castling: true,
+                    ..Move::new(pos, king_to, false)
+                });
+            }
+        }
+    }
+
+    pub fn fifty_move_rule_draw(&self) -> bool {
+        self.ply - self.fifty_move_rule_last_event >= 150
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Start,
+    Captures,
+    Promotions,
+    Quiets,
+    Done,
+}
+
+// Hands out the legal moves stage by stage: captures (the best exchanges
+// first, see see.rs), then promotions which do not capture, then quiet
+// moves and drops. Each stage is only generated when the previous one is
+// used up and legality is only checked for the move which is handed out,
+// so a search which cuts off early skips most of the work. The pawn pushes
+// are generated together with the promotions, the other quiet moves only
+// when they are needed.
+//
+// The picker does not borrow the GameState so that the moves can be made
+// and unmade in between. It has to be given the same position each time.
+pub struct MovePicker {
+    stage: Stage,
+    // The pseudo legal moves of the current stage, the next one last.
+    moves: Vec<Move>,
+    // Pawn pushes which were generated with the promotions.
+    quiets: Vec<Move>,
+    found_capture: bool,
+}
+
+impl Default for MovePicker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MovePicker {
+    pub fn new() -> Self {
+        MovePicker {
+            stage: Stage::Start,
+            moves: Vec::new(),
+            quiets: Vec::new(),
+            found_capture: false,
+        }
+    }
+
+    pub fn next_move(&mut self, game_state: &GameState) -> Option<Move> {
+        let rules = game_state.variant.rules();
+        loop {
+            if let Some(mv) = self.moves.pop() {
+                if rules.is_legal(game_state, mv) {
+                    self.found_capture |= mv.capture;
+                    return Some(mv);
                 }
+                continue;
             }
-            PieceType::Pawn => {
-                let (final_row, move_moves, capture_moves) = get_pawn_moves(self.turn());
-                for (new_pos, _) in
-                    self.board
-                        .get_far_moves(pos, move_moves, 1, true, false, self.turn())
-                {
-                    let new_pos_and_state = self.new_state_from_to(piece, pos, new_pos);
-                    if new_pos / 8 == final_row {
-                        for promoted_pos_and_state in
-                            self.generate_pawn_promotions(new_pos_and_state).iter()
-                        {
-                            new_states.push(*promoted_pos_and_state);
-                        }
-                    } else {
-                        new_states.push(new_pos_and_state);
-                    }
+            self.stage = match self.stage {
+                Stage::Start => {
+                    self.generate_captures(game_state);
+                    Stage::Captures
                 }
-                for (new_pos, _) in
-                    self.board
-                        .get_far_moves(pos, capture_moves, 1, false, true, self.turn())
-                {
-                    let new_pos_and_state = self.new_state_from_to(piece, pos, new_pos);
-                    if new_pos / 8 == final_row {
-                        for promoted_pos_and_state in
-                            self.generate_pawn_promotions(new_pos_and_state).iter()
-                        {
-                            new_states.push(*promoted_pos_and_state);
-                        }
-                    } else {
-                        new_states.push(new_pos_and_state);
-                    }
+                Stage::Captures if rules.compulsory_captures() && self.found_capture => {
+                    Stage::Done
                 }
-                if self.ply == self.board.en_passant_field.ply + 1 {
-                    for (new_pos, _) in
-                        self.board
-                            .get_far_moves(pos, capture_moves, 1, true, false, self.turn())
-                    {
-                        // No promotions while capturing en-passant possible
-                        if self.board.en_passant_field.skipped == new_pos {
-                            let mut new_pos_and_state = self.new_state_from_to(piece, pos, new_pos);
-                            new_pos_and_state.1.board.fields[self.board.en_passant_field.target] =
-                                None;
-                            new_states.push(new_pos_and_state);
-                        }
-                    }
+                Stage::Captures => {
+                    self.generate_promotions(game_state);
+                    Stage::Promotions
+                }
+                Stage::Promotions => {
+                    self.generate_quiets(game_state);
+                    Stage::Quiets
+                }
+                Stage::Quiets | Stage::Done => {
+                    self.stage = Stage::Done;
+                    return None;
                 }
+            };
+        }
+    }
+
+    fn generate_captures(&mut self, game_state: &GameState) {
+        for (piece, pos) in game_state.board.get_pieces_with_pos(game_state.turn()) {
+            game_state.push_pseudo_legal_captures(&mut self.moves, piece, pos);
+        }
+        self.moves.sort_by_cached_key(|&mv| game_state.board.see(mv));
+    }
+
+    // The pawn pushes which do not promote are kept for the quiet moves.
+    fn generate_promotions(&mut self, game_state: &GameState) {
+        let mut pushes = Vec::new();
+        for (piece, pos) in game_state.board.get_pieces_with_pos(game_state.turn()) {
+            if let PieceType::InitPawn | PieceType::Pawn = piece {
+                game_state.push_pseudo_legal_non_captures(&mut pushes, piece, pos);
             }
         }
-        new_states
+        let (promotions, quiets) = pushes.into_iter().partition(|mv| mv.promotion.is_some());
+        self.moves = promotions;
+        self.moves.reverse();
+        self.quiets = quiets;
     }
 
-    pub fn fifty_move_rule_draw(&self) -> bool {
-        self.ply - self.fifty_move_rule_last_event >= 150
+    fn generate_quiets(&mut self, game_state: &GameState) {
+        for (piece, pos) in game_state.board.get_pieces_with_pos(game_state.turn()) {
+            if let PieceType::InitPawn | PieceType::Pawn = piece {
+                continue;
+            }
+            game_state.push_pseudo_legal_non_captures(&mut self.quiets, piece, pos);
+        }
+        if game_state.variant.rules().uses_pockets() {
+            self.quiets.extend(game_state.get_drop_moves());
+        }
+        self.moves = std::mem::take(&mut self.quiets);
+        self.moves.reverse();
     }
 }
 
@@ -588,7 +1104,7 @@ impl GameState {
 // to determine (synchronously) if a game is finished. In elm, this is
 // harder since all communication to wasm happens via ports which are 
 // asynchronous.
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AnnotatedGameState {
     #[serde(flatten)]
     pub game: GameState,
@@ -596,9 +1112,62 @@ pub struct AnnotatedGameState {
 }
 impl From<GameState> for AnnotatedGameState {
     fn from(game: GameState) -> Self {
-        AnnotatedGameState {
-            game,
-            finished: game.get_finished_state(),
+        let finished = game.get_finished_state();
+        AnnotatedGameState { game, finished }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_picker_stages() {
+        let game_state = GameState::from_fen("1k6/6P1/8/3p4/4p3/2N5/8/1K6 w - - 0 1").unwrap();
+        let moves: Vec<Move> = game_state.legal_moves().collect();
+        let pos = |name| pos_from_algebraic(name).unwrap();
+        // The undefended pawn first, then the defended one.
+        assert_eq!(moves[0], Move::new(pos("c3"), pos("d5"), true));
+        assert_eq!(moves[1], Move::new(pos("c3"), pos("e4"), true));
+        assert_eq!(moves[2].promotion, Some(PieceType::Queen));
+        assert!(moves[6..].iter().all(|mv| !mv.capture && mv.promotion.is_none()));
+    }
+
+    #[test]
+    fn en_passant_json() {
+        let mut game_state = GameState::new(VariantKind::Standard);
+        let json = serde_json::to_string(&game_state.board).unwrap();
+        assert!(json.ends_with(r#""en_passant_field":{"ply":0,"skipped":255,"target":255}}"#));
+        assert_eq!(serde_json::from_str::<BoardState>(&json).unwrap(), game_state.board);
+        let mv = game_state.parse_uci("e2e4").unwrap();
+        game_state.make_move(mv);
+        let json = serde_json::to_string(&game_state.board).unwrap();
+        assert!(json.ends_with(r#""en_passant_field":{"ply":0,"skipped":20,"target":28}}"#));
+        assert_eq!(serde_json::from_str::<BoardState>(&json).unwrap(), game_state.board);
+    }
+
+    #[test]
+    fn repetitions_survive_json() {
+        let mut game_state = GameState::new(VariantKind::Standard);
+        for uci in &["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8"] {
+            let mv = game_state.parse_uci(uci).unwrap();
+            game_state.make_move(mv);
+        }
+        assert_eq!(game_state.repetition_count(), 3);
+        let json = serde_json::to_string(&game_state).unwrap();
+        assert!(json.contains(r#""history":[""#));
+        let game_state: GameState = serde_json::from_str(&json).unwrap();
+        assert_eq!(game_state.repetition_count(), 3);
+    }
+
+    #[test]
+    fn double_step_without_capture_repeats() {
+        let mut game_state = GameState::new(VariantKind::Standard);
+        for uci in "e2e4 g8f6 g1f3 f6g8 f3g1 g8f6 g1f3 f6g8 f3g1".split(' ') {
+            let mv = game_state.parse_uci(uci).unwrap();
+            game_state.make_move(mv);
         }
+        assert_eq!(game_state.repetition_count(), 3);
+        assert!(game_state.verify_position_key());
     }
 }
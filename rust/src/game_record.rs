@@ -0,0 +1,516 @@
+// A recorded game: every move played from the initial position, kept as a
+// tree so that alternatives can be explored and taken back. The first child
+// of a node is the main continuation (the mainline), the others are
+// variations. The GameState of the current node is kept up to date, so the
+// front ends navigate with undo(), redo() and go_to() instead of replaying
+// the moves themselves. The record also knows how the game ended (see
+// result.rs) and whether a draw has been offered.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::ChaiError;
+use crate::game::*;
+use crate::pgn::{PgnGame, PgnMove, PgnResult};
+use crate::result::{GameResult, Termination};
+
+pub type NodeId = usize;
+
+// The initial position, the only node without a move.
+pub const ROOT: NodeId = 0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    pub mv: Option<Move>,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    initial: GameState,
+    nodes: Vec<Node>,
+    current: NodeId,
+    state: GameState,
+    // The moves from the root to current, to take them back.
+    undos: Vec<UndoInfo>,
+    // Set when the players end the game, the position ending it is not
+    // stored.
+    result: Option<GameResult>,
+    // The player who offered a draw which the opponent has not answered.
+    draw_offer: Option<Player>,
+}
+
+impl Game {
+    pub fn new(initial: GameState) -> Self {
+        Game {
+            state: initial.clone(),
+            initial,
+            nodes: vec![Node {
+                mv: None,
+                parent: None,
+                children: Vec::new(),
+            }],
+            current: ROOT,
+            undos: Vec::new(),
+            result: None,
+            draw_offer: None,
+        }
+    }
+
+    pub fn initial(&self) -> &GameState {
+        &self.initial
+    }
+
+    // The position at the current node.
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn current(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    // Plays the move at the current node. A move which has been played
+    // there before is not recorded again, its node becomes current.
+    // Otherwise the move starts a new variation, or continues the mainline
+    // if it is the first move played there.
+    pub fn play(&mut self, mv: Move) -> Result<NodeId, ChaiError> {
+        let player = self.state.turn();
+        let existing = self.nodes[self.current]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].mv == Some(mv));
+        let id = match existing {
+            Some(child) => {
+                self.enter(child);
+                child
+            }
+            None => {
+                let undo = self.state.try_make_move(mv)?;
+                self.undos.push(undo);
+                let id = self.nodes.len();
+                self.nodes.push(Node {
+                    mv: Some(mv),
+                    parent: Some(self.current),
+                    children: Vec::new(),
+                });
+                self.nodes[self.current].children.push(id);
+                self.current = id;
+                id
+            }
+        };
+        // Moving instead of answering declines a draw offer.
+        if self.draw_offer == Some(player.opponent()) {
+            self.draw_offer = None;
+        }
+        Ok(id)
+    }
+
+    // Takes back the last move, false at the initial position.
+    pub fn undo(&mut self) -> bool {
+        let node = &self.nodes[self.current];
+        let (mv, parent) = match (node.mv, node.parent) {
+            (Some(mv), Some(parent)) => (mv, parent),
+            _ => return false,
+        };
+        let undo = self.undos.pop().expect("Every move on the path was made.");
+        self.state.unmake_move(mv, undo);
+        self.current = parent;
+        true
+    }
+
+    // Follows the main continuation of the current node, false if there is
+    // none.
+    pub fn redo(&mut self) -> bool {
+        match self.nodes[self.current].children.first() {
+            Some(&child) => {
+                self.enter(child);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Makes the move of a child of the current node, which is known to be
+    // legal.
+    fn enter(&mut self, child: NodeId) {
+        let mv = self.nodes[child].mv.expect("Only the root has no move.");
+        self.undos.push(self.state.make_move(mv));
+        self.current = child;
+    }
+
+    // The nodes from the root (excluded) to id (included).
+    fn path(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = Vec::new();
+        let mut node = id;
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(node);
+            node = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    // Goes back to the last node which id and the current node have in
+    // common and plays the moves to id from there.
+    pub fn go_to(&mut self, id: NodeId) -> Result<(), ChaiError> {
+        if id >= self.nodes.len() {
+            return Err(ChaiError::InvalidNode(id));
+        }
+        let path = self.path(id);
+        while self.current != ROOT && !path.contains(&self.current) {
+            self.undo();
+        }
+        let start = path.iter().position(|&node| node == self.current).map_or(0, |i| i + 1);
+        for &node in path[start..].iter() {
+            self.enter(node);
+        }
+        Ok(())
+    }
+
+    // The moves from the initial position to the current node.
+    pub fn moves(&self) -> Vec<Move> {
+        self.path(self.current)
+            .into_iter()
+            .filter_map(|node| self.nodes[node].mv)
+            .collect()
+    }
+
+    pub fn mainline(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut node = ROOT;
+        while let Some(&child) = self.nodes[node].children.first() {
+            moves.extend(self.nodes[child].mv);
+            node = child;
+        }
+        moves
+    }
+
+    // The alternatives at the current node, the main continuation first.
+    pub fn variations(&self) -> &[NodeId] {
+        &self.nodes[self.current].children
+    }
+
+    // Makes the variation containing id the mainline: id and all of its
+    // ancestors become the main continuation of their parents.
+    pub fn promote_to_mainline(&mut self, id: NodeId) -> Result<(), ChaiError> {
+        if id >= self.nodes.len() {
+            return Err(ChaiError::InvalidNode(id));
+        }
+        for node in self.path(id) {
+            let parent = self.nodes[node].parent.expect("The root is not on a path.");
+            let children = &mut self.nodes[parent].children;
+            let index = children.iter().position(|&child| child == node);
+            let index = index.expect("A node is a child of its parent.");
+            children[..=index].rotate_right(1);
+        }
+        Ok(())
+    }
+
+    // How the game ended: by the players or by the current position. Moves
+    // can still be played and taken back afterwards, e.g. to analyze the
+    // game, but the result stays.
+    pub fn result(&self) -> Option<GameResult> {
+        self.result.or_else(|| GameResult::from_position(&self.state))
+    }
+
+    pub fn draw_offer(&self) -> Option<Player> {
+        self.draw_offer
+    }
+
+    fn end(&mut self, result: GameResult) -> Result<GameResult, ChaiError> {
+        if self.result().is_some() {
+            return Err(ChaiError::GameOver);
+        }
+        self.result = Some(result);
+        self.draw_offer = None;
+        Ok(result)
+    }
+
+    pub fn resign(&mut self, player: Player) -> Result<GameResult, ChaiError> {
+        self.end(GameResult::resignation(player))
+    }
+
+    // The flag of player fell, see clock.rs.
+    pub fn flag(&mut self, player: Player) -> Result<GameResult, ChaiError> {
+        let result = GameResult::time_forfeit(&self.state, player);
+        self.end(result)
+    }
+
+    // The offer stands until the opponent answers it or makes a move.
+    pub fn offer_draw(&mut self, player: Player) -> Result<(), ChaiError> {
+        if self.result().is_some() {
+            return Err(ChaiError::GameOver);
+        }
+        self.draw_offer = Some(player);
+        Ok(())
+    }
+
+    pub fn accept_draw(&mut self, player: Player) -> Result<GameResult, ChaiError> {
+        if self.draw_offer != Some(player.opponent()) {
+            return Err(ChaiError::NoDrawOffer);
+        }
+        self.end(GameResult::draw(Termination::AgreedDraw))
+    }
+
+    pub fn decline_draw(&mut self, player: Player) -> Result<(), ChaiError> {
+        if self.draw_offer != Some(player.opponent()) {
+            return Err(ChaiError::NoDrawOffer);
+        }
+        self.draw_offer = None;
+        Ok(())
+    }
+
+    // A draw by threefold repetition or the fifty-move rule, claimed by
+    // the player to move.
+    pub fn claim_draw(&mut self) -> Result<GameResult, ChaiError> {
+        let claim = self.state.draw_claim().ok_or(ChaiError::NoDrawClaim)?;
+        self.end(claim)
+    }
+
+    // Comments and NAGs are not part of the tree, they are dropped.
+    pub fn from_pgn(pgn: &PgnGame) -> Result<Self, ChaiError> {
+        let initial = pgn
+            .initial_state()
+            .map_err(|error| ChaiError::InvalidPgn(error.to_string()))?;
+        let mut game = Game::new(initial);
+        game.add_pgn_line(&pgn.moves)?;
+        Ok(game)
+    }
+
+    fn add_pgn_line(&mut self, moves: &[PgnMove]) -> Result<(), ChaiError> {
+        for pgn_move in moves {
+            let parent = self.current;
+            let main = self.play(pgn_move.mv)?;
+            for variation in pgn_move.variations.iter() {
+                self.go_to(parent)?;
+                self.add_pgn_line(variation)?;
+            }
+            self.go_to(main)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_pgn(&self) -> PgnGame {
+        let result = self.result();
+        let pgn_result = result.map_or(PgnResult::Unknown, |result| result.pgn_result());
+        let mut pgn = PgnGame::from_moves(&self.initial, &[], pgn_result);
+        if let Some(result) = result {
+            pgn.set_tag("Termination", result.pgn_termination());
+        }
+        if let Some(&first) = self.nodes[ROOT].children.first() {
+            pgn.moves = self.pgn_line(first, true);
+        }
+        pgn
+    }
+
+    // The line starting at the node start, following the main
+    // continuations. The other children become the variations. The
+    // siblings of start itself are only added if start is a main
+    // continuation, a variation does not repeat its alternatives.
+    fn pgn_line(&self, start: NodeId, siblings: bool) -> Vec<PgnMove> {
+        let mut line = Vec::new();
+        let mut id = start;
+        let mut with_variations = siblings;
+        loop {
+            let node = &self.nodes[id];
+            let mut pgn_move = PgnMove::new(node.mv.expect("Only the root has no move."));
+            if with_variations {
+                let parent = node.parent.expect("Only the root has no parent.");
+                pgn_move.variations = self.nodes[parent].children[1..]
+                    .iter()
+                    .map(|&variation| self.pgn_line(variation, false))
+                    .collect();
+            }
+            line.push(pgn_move);
+            with_variations = true;
+            match node.children.first() {
+                Some(&child) => id = child,
+                None => return line,
+            }
+        }
+    }
+
+    // Rebuilds a game from its serialized parts. Everything is checked
+    // since it may come from a front end: the tree structure and every
+    // move.
+    fn from_parts(initial: GameState, nodes: Vec<Node>, current: NodeId) -> Result<Self, ChaiError> {
+        initial.validate().map_err(ChaiError::InvalidPosition)?;
+        match nodes.first() {
+            Some(root) if root.mv.is_none() && root.parent.is_none() => {}
+            _ => return Err(ChaiError::InvalidNode(ROOT)),
+        }
+        // Every node is the child of its parent and nothing else, so the
+        // nodes form a tree if all of them can be reached from the root.
+        for (id, node) in nodes.iter().enumerate().skip(1) {
+            let parent = node.parent.and_then(|parent| nodes.get(parent));
+            match parent {
+                Some(parent) if node.mv.is_some() && parent.children.contains(&id) => {}
+                _ => return Err(ChaiError::InvalidNode(id)),
+            }
+        }
+        for (id, node) in nodes.iter().enumerate() {
+            for &child in node.children.iter() {
+                if nodes.get(child).map(|child| child.parent) != Some(Some(id)) {
+                    return Err(ChaiError::InvalidNode(child));
+                }
+            }
+        }
+        let children: usize = nodes.iter().map(|node| node.children.len()).sum();
+        let mut state = initial.clone();
+        if children != nodes.len() - 1 || check_moves(&mut state, &nodes, ROOT)? != nodes.len() {
+            return Err(ChaiError::InvalidNode(ROOT));
+        }
+        let mut game = Game::new(initial);
+        game.nodes = nodes;
+        game.go_to(current)?;
+        Ok(game)
+    }
+}
+
+// Checks that the moves below id are legal, state is the position at id.
+// Returns the number of nodes in the subtree.
+fn check_moves(state: &mut GameState, nodes: &[Node], id: NodeId) -> Result<usize, ChaiError> {
+    let mut count = 1;
+    for &child in nodes[id].children.iter() {
+        let mv = nodes[child].mv.expect("Only the root has no move.");
+        let undo = state.try_make_move(mv)?;
+        count += check_moves(state, nodes, child)?;
+        state.unmake_move(mv, undo);
+    }
+    Ok(count)
+}
+
+// The current position is sent along for the front ends, it is not read
+// back but replayed from the moves.
+#[derive(Serialize, Deserialize)]
+struct SerdeGame {
+    initial: GameState,
+    nodes: Vec<Node>,
+    current: NodeId,
+    #[serde(default)]
+    result: Option<GameResult>,
+    #[serde(default)]
+    draw_offer: Option<Player>,
+    #[serde(default, skip_deserializing)]
+    position: Option<AnnotatedGameState>,
+}
+
+impl Serialize for Game {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerdeGame {
+            initial: self.initial.clone(),
+            nodes: self.nodes.clone(),
+            current: self.current,
+            result: self.result,
+            draw_offer: self.draw_offer,
+            position: Some(AnnotatedGameState::from(self.state.clone())),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Game {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let sg = SerdeGame::deserialize(deserializer)?;
+        let mut game =
+            Game::from_parts(sg.initial, sg.nodes, sg.current).map_err(de::Error::custom)?;
+        game.result = sg.result;
+        game.draw_offer = sg.draw_offer;
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::{read_pgn, write_pgn};
+    use crate::variant::VariantKind;
+
+    fn play(game: &mut Game, uci: &str) -> NodeId {
+        let mv = game.state().parse_uci(uci).unwrap();
+        game.play(mv).unwrap()
+    }
+
+    #[test]
+    fn undo_redo_and_variations() {
+        let mut game = Game::new(GameState::new(VariantKind::Standard));
+        let e4 = play(&mut game, "e2e4");
+        play(&mut game, "e7e5");
+        assert!(game.undo());
+        let c5 = play(&mut game, "c7c5");
+        assert_eq!(game.variations(), &[] as &[NodeId]);
+        game.go_to(e4).unwrap();
+        assert_eq!(game.variations().len(), 2);
+        assert!(game.redo());
+        assert_eq!(game.state().to_uci(game.mainline()[1]), "e7e5");
+        assert_eq!(game.state().ply, 2);
+        game.promote_to_mainline(c5).unwrap();
+        let mainline: Vec<String> = game.mainline().iter().map(|&mv| game.initial().to_uci(mv)).collect();
+        assert_eq!(mainline, ["e2e4", "c7c5"]);
+        // Playing a recorded move again does not add a node.
+        game.go_to(e4).unwrap();
+        assert_eq!(play(&mut game, "c7c5"), c5);
+        assert_eq!(game.nodes().len(), 4);
+        let e4_again = game.mainline()[0];
+        assert_eq!(game.play(e4_again), Err(ChaiError::IllegalMove(e4_again)));
+        assert!(game.go_to(4).is_err());
+    }
+
+    #[test]
+    fn json_and_pgn() {
+        let mut game = Game::new(GameState::new(VariantKind::Standard));
+        play(&mut game, "d2d4");
+        play(&mut game, "d7d5");
+        game.undo();
+        play(&mut game, "g8f6");
+        play(&mut game, "c2c4");
+        let json = serde_json::to_string(&game).unwrap();
+        let restored: Game = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.nodes(), game.nodes());
+        assert_eq!(restored.state(), game.state());
+
+        let pgn = write_pgn(&game.to_pgn()).unwrap();
+        assert!(pgn.contains("1. d4 d5 (1... Nf6 2. c4) *"));
+        let from_pgn = Game::from_pgn(&read_pgn(&pgn).unwrap()[0]).unwrap();
+        assert_eq!(from_pgn.nodes(), game.nodes());
+
+        // The moves are checked, too.
+        let illegal = json.replace(r#""from":11"#, r#""from":12"#);
+        assert!(serde_json::from_str::<Game>(&illegal).is_err());
+    }
+
+    #[test]
+    fn draw_offers_and_resignation() {
+        let mut game = Game::new(GameState::new(VariantKind::Standard));
+        game.offer_draw(Player::White).unwrap();
+        assert_eq!(game.accept_draw(Player::White), Err(ChaiError::NoDrawOffer));
+        // Black declines by moving.
+        play(&mut game, "e2e4");
+        let illegal = Move::new(52, 28, false);
+        assert_eq!(game.play(illegal), Err(ChaiError::IllegalMove(illegal)));
+        assert_eq!(game.draw_offer(), Some(Player::White));
+        play(&mut game, "e7e5");
+        assert_eq!(game.draw_offer(), None);
+        game.offer_draw(Player::White).unwrap();
+        let result = game.accept_draw(Player::Black).unwrap();
+        assert_eq!(result.termination, Termination::AgreedDraw);
+        assert_eq!(game.resign(Player::White), Err(ChaiError::GameOver));
+        assert!(write_pgn(&game.to_pgn()).unwrap().contains("1. e4 e5 1/2-1/2"));
+
+        let mut game = Game::new(GameState::new(VariantKind::Standard));
+        assert_eq!(game.claim_draw(), Err(ChaiError::NoDrawClaim));
+        game.resign(Player::White).unwrap();
+        let restored: Game = serde_json::from_str(&serde_json::to_string(&game).unwrap()).unwrap();
+        assert_eq!(restored.result().unwrap().winner, Some(Player::Black));
+    }
+}
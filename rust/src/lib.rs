@@ -20,45 +20,278 @@ pub fn main() -> Result<(), JsValue> {
 }
 */
 
-mod game;
-mod minimax;
+pub mod antichess;
+pub mod atomic;
+pub mod attacks;
+pub mod bitboard;
+pub mod chess960;
+pub mod clock;
+pub mod crazyhouse;
+pub mod error;
+pub mod fen;
+pub mod game;
+pub mod game_record;
+pub mod minimax;
+pub mod perft;
+pub mod pgn;
+pub mod result;
+pub mod san;
+pub mod see;
+pub mod square;
+pub mod uci;
+pub mod validation;
+pub mod variant;
+pub mod zobrist;
 mod util;
 
+use error::ChaiError;
+use square::Square;
+use std::convert::TryFrom;
+
+// Errors are thrown as strings on the JavaScript side so that the front end
+// can display them.
+impl From<ChaiError> for JsValue {
+    fn from(error: ChaiError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+// Without a variant name, a standard game is started.
 #[wasm_bindgen]
-pub fn new_game() -> String {
+pub fn new_game(variant: Option<String>) -> Result<String, JsValue> {
     console_error_panic_hook::set_once();
-    let board = game::BoardState::new();
-    let game = game::GameState {
-        board,
-        ply: 0,
-        fifty_move_rule_last_event: 0,
+    let variant = match variant {
+        Some(name) => variant::VariantKind::from_name(&name).ok_or(ChaiError::UnknownVariant(name))?,
+        None => variant::VariantKind::Standard,
     };
-    gamestate_to_json(game)
+    Ok(gamestate_to_json(game::GameState::new(variant))?)
 }
 
-fn get_gamestate_from_json(json_game: String) -> game::GameState {
-    let agame: game::AnnotatedGameState = serde_json::from_str(&json_game).unwrap();
-    agame.game
+fn get_gamestate_from_json(json_game: String) -> Result<game::GameState, ChaiError> {
+    let agame: game::AnnotatedGameState = serde_json::from_str(&json_game)?;
+    // Reject impossible positions before the move generator runs into them.
+    agame.game.validate().map_err(ChaiError::InvalidPosition)?;
+    Ok(agame.game)
 }
 
-fn gamestate_to_json(game: game::GameState) -> String {
+fn gamestate_to_json(game: game::GameState) -> Result<String, ChaiError> {
     let agame = game::AnnotatedGameState::from(game);
-    serde_json::to_string(&agame).unwrap()
+    Ok(serde_json::to_string(&agame)?)
+}
+
+#[wasm_bindgen]
+pub fn get_legal_moves_for_single_piece(json_game: String, field: usize) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    let field = Square::try_from(field)?;
+    let new_pos_and_states: Vec<(Square, game::AnnotatedGameState)> = game.get_legal_moves_for_single_piece(field.index()).into_iter().map(|mv| (Square::at(mv.to), game::AnnotatedGameState::from(game.after_move(mv)))).collect();
+    Ok(serde_json::to_string(&new_pos_and_states).map_err(ChaiError::from)?)
+}
+
+#[wasm_bindgen]
+pub fn get_minimax_move(json_game: String) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    let mv = minimax::choose_move(&game, 3, &minimax::weighted_piececount)?;
+    Ok(gamestate_to_json(game.after_move(mv))?)
+}
+
+// Checkers, pins and how often each field is attacked, see attacks.rs.
+#[wasm_bindgen]
+pub fn get_attack_info(json_game: String) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    let info = attacks::AttackInfo::new(&game);
+    Ok(serde_json::to_string(&info).map_err(ChaiError::from)?)
+}
+
+// The fields of the black and of the white pieces which attack the field.
+#[wasm_bindgen]
+pub fn get_attackers(json_game: String, field: usize) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    let field = Square::try_from(field)?;
+    let attackers = [game::Player::Black, game::Player::White]
+        .map(|player| bitboard::fields(game.board.attackers(field.index(), player)).map(Square::at).collect::<Vec<_>>());
+    Ok(serde_json::to_string(&attackers).map_err(ChaiError::from)?)
+}
+
+// A game record with undo/redo and variations, see game_record.rs. Every
+// function returns the record with the position at the current node.
+fn get_record_from_json(json_record: String) -> Result<game_record::Game, ChaiError> {
+    Ok(serde_json::from_str(&json_record)?)
+}
+
+fn record_to_json(record: &game_record::Game) -> Result<String, ChaiError> {
+    Ok(serde_json::to_string(record)?)
+}
+
+#[wasm_bindgen]
+pub fn new_record(json_game: String) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    Ok(record_to_json(&game_record::Game::new(game))?)
+}
+
+#[wasm_bindgen]
+pub fn record_play(json_record: String, json_move: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    let mv: game::Move = serde_json::from_str(&json_move).map_err(ChaiError::from)?;
+    record.play(mv)?;
+    Ok(record_to_json(&record)?)
+}
+
+#[wasm_bindgen]
+pub fn record_minimax_move(json_record: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    let mv = minimax::choose_move(record.state(), 3, &minimax::weighted_piececount)?;
+    record.play(mv)?;
+    Ok(record_to_json(&record)?)
+}
+
+#[wasm_bindgen]
+pub fn record_undo(json_record: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    record.undo();
+    Ok(record_to_json(&record)?)
+}
+
+#[wasm_bindgen]
+pub fn record_redo(json_record: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    record.redo();
+    Ok(record_to_json(&record)?)
+}
+
+#[wasm_bindgen]
+pub fn record_go_to(json_record: String, node: usize) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    record.go_to(node)?;
+    Ok(record_to_json(&record)?)
 }
 
 #[wasm_bindgen]
-pub fn get_legal_moves_for_single_piece(json_game: String, field: usize) -> String {
-    let game = get_gamestate_from_json(json_game);
-    let piece = game.board.fields[field].unwrap().0;
-    let new_pos_and_states: Vec<(usize, game::AnnotatedGameState)> = game.get_legal_moves_for_single_piece(piece, field).into_iter().map(|pos_and_state| (pos_and_state.0, game::AnnotatedGameState::from(pos_and_state.1))).collect();
-    serde_json::to_string(&new_pos_and_states).unwrap()
+pub fn record_promote_to_mainline(json_record: String, node: usize) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    record.promote_to_mainline(node)?;
+    Ok(record_to_json(&record)?)
 }
 
+// The player is "White" or "Black" in JSON.
 #[wasm_bindgen]
-pub fn get_minimax_move(json_game: String) -> String {
-    let game = get_gamestate_from_json(json_game);
-    let alphabeta_res = minimax::alphabeta_init(game, 3, &minimax::weighted_piececount);
-    let new_states = alphabeta_res.1;
-    let new_state = util::choose(new_states).unwrap();
-    gamestate_to_json(new_state)
+pub fn record_resign(json_record: String, json_player: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    let player: game::Player = serde_json::from_str(&json_player).map_err(ChaiError::from)?;
+    record.resign(player)?;
+    Ok(record_to_json(&record)?)
+}
+
+// The player to move offers a draw and the engine answers right away.
+#[wasm_bindgen]
+pub fn record_offer_draw(json_record: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    let player = record.state().turn();
+    record.offer_draw(player)?;
+    let engine = player.opponent();
+    if minimax::accepts_draw(record.state(), engine, 3, &minimax::weighted_piececount) {
+        record.accept_draw(engine)?;
+    } else {
+        record.decline_draw(engine)?;
+    }
+    Ok(record_to_json(&record)?)
+}
+
+#[wasm_bindgen]
+pub fn record_claim_draw(json_record: String) -> Result<String, JsValue> {
+    let mut record = get_record_from_json(json_record)?;
+    record.claim_draw()?;
+    Ok(record_to_json(&record)?)
+}
+
+// The result as JSON, null while the game is going on.
+#[wasm_bindgen]
+pub fn get_record_result(json_record: String) -> Result<String, JsValue> {
+    let record = get_record_from_json(json_record)?;
+    Ok(serde_json::to_string(&record.result()).map_err(ChaiError::from)?)
+}
+
+// The record as PGN text, with the moves which were taken back as
+// variations.
+#[wasm_bindgen]
+pub fn record_to_pgn(json_record: String) -> Result<String, JsValue> {
+    let record = get_record_from_json(json_record)?;
+    let pgn = pgn::write_pgn(&record.to_pgn())
+        .map_err(|error| ChaiError::InvalidPgn(error.to_string()))?;
+    Ok(pgn)
+}
+
+// Reads the first game of the PGN text.
+#[wasm_bindgen]
+pub fn record_from_pgn(pgn_text: String) -> Result<String, JsValue> {
+    let games = pgn::read_pgn(&pgn_text).map_err(|error| ChaiError::InvalidPgn(error.to_string()))?;
+    let pgn = games
+        .first()
+        .ok_or_else(|| ChaiError::InvalidPgn("no game found".to_string()))?;
+    Ok(record_to_json(&game_record::Game::from_pgn(pgn)?)?)
+}
+
+// Chess clocks, see clock.rs. The front end measures the time and passes
+// the milliseconds the player to move has been thinking.
+fn get_clock_from_json(json_clock: String) -> Result<clock::Clock, ChaiError> {
+    let clock: clock::Clock = serde_json::from_str(&json_clock)?;
+    if clock.control.stages.is_empty() {
+        return Err(ChaiError::InvalidTimeControl("no stages".to_string()));
+    }
+    Ok(clock)
+}
+
+fn clock_to_json(clock: &clock::Clock) -> Result<String, ChaiError> {
+    Ok(serde_json::to_string(clock)?)
+}
+
+// The control is written like the PGN TimeControl tag, e.g. "300+2".
+#[wasm_bindgen]
+pub fn new_clock(json_game: String, control: String) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    let control: clock::TimeControl = control.parse().map_err(ChaiError::from)?;
+    Ok(clock_to_json(&clock::Clock::new(control, game.turn()))?)
+}
+
+#[wasm_bindgen]
+pub fn clock_press(json_clock: String, elapsed: f64) -> Result<String, JsValue> {
+    let mut clock = get_clock_from_json(json_clock)?;
+    clock.press(elapsed.max(0.0) as clock::Millis);
+    Ok(clock_to_json(&clock)?)
+}
+
+#[wasm_bindgen]
+pub fn clock_check_flag(json_clock: String, elapsed: f64) -> Result<String, JsValue> {
+    let mut clock = get_clock_from_json(json_clock)?;
+    clock.check_flag(elapsed.max(0.0) as clock::Millis);
+    Ok(clock_to_json(&clock)?)
+}
+
+// The times to show for Black and White.
+#[wasm_bindgen]
+pub fn clock_display(json_clock: String, elapsed: f64) -> Result<String, JsValue> {
+    let clock = get_clock_from_json(json_clock)?;
+    let display = clock.display(elapsed.max(0.0) as clock::Millis);
+    Ok(serde_json::to_string(&display).map_err(ChaiError::from)?)
+}
+
+// The winner if the player to move ran out of time, null for a draw.
+#[wasm_bindgen]
+pub fn get_flag_winner(json_game: String) -> Result<String, JsValue> {
+    let game = get_gamestate_from_json(json_game)?;
+    let winner = clock::flag_winner(&game, game.turn());
+    Ok(serde_json::to_string(&winner).map_err(ChaiError::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inconsistent_counters_from_json() {
+        let json = gamestate_to_json(game::GameState::new(variant::VariantKind::Standard)).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["fifty_move_rule_last_event"] = 10.into();
+        let result = get_gamestate_from_json(value.to_string());
+        assert!(matches!(result, Err(ChaiError::InvalidPosition(_))));
+    }
 }
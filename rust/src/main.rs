@@ -1,5 +1,5 @@
-use rand::seq::SliceRandom;
 use std::collections::HashSet;
+use std::io::Write;
 use std::time;
 
 use crossterm::event;
@@ -7,29 +7,88 @@ use tui::layout::Constraint;
 use tui::widgets::Paragraph;
 
 pub mod board_view;
+pub mod antichess;
+pub mod atomic;
+pub mod attacks;
+pub mod bitboard;
+pub mod chess960;
+pub mod clock;
+pub mod crazyhouse;
+pub mod error;
+pub mod fen;
 pub mod game;
+pub mod game_record;
 pub mod minimax;
+pub mod perft;
+pub mod pgn;
+pub mod result;
+pub mod san;
+pub mod see;
+pub mod square;
+pub mod uci;
+pub mod validation;
+pub mod variant;
+pub mod zobrist;
 pub mod ui;
 mod util;
 
 use util::ms;
+use square::Square;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     //serde_test();
     //tui_test()?;
     //autoplay()?;
-    play_as(game::Player::White)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("perft") => run_perft(&args[1..], false)?,
+        Some("divide") => run_perft(&args[1..], true)?,
+        // chai [variant] [time control], e.g. chai Standard 300+2
+        Some(name) => {
+            let variant = variant::VariantKind::from_name(name).ok_or_else(|| {
+                let names: Vec<&str> =
+                    variant::VariantKind::ALL.iter().map(|variant| variant.name()).collect();
+                format!("Unknown variant '{}', try one of: {}", name, names.join(", "))
+            })?;
+            let control = match args.get(1) {
+                Some(control) => Some(control.parse::<clock::TimeControl>()?),
+                None => None,
+            };
+            play_as(game::Player::White, variant, control)?
+        }
+        None => play_as(game::Player::White, variant::VariantKind::Standard, None)?,
+    }
 
     Ok(())
 }
 
-pub fn serde_test() {
-    let board = game::BoardState::new();
-    let game = game::GameState {
-        board,
-        ply: 0,
-        fifty_move_rule_last_event: 0
+// chai perft|divide <depth> [fen]
+pub fn run_perft(args: &[String], divide: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let depth: usize = args
+        .first()
+        .ok_or("Usage: chai perft|divide <depth> [fen]")?
+        .parse()?;
+    let mut game = if args.len() > 1 {
+        game::GameState::from_fen(&args[1..].join(" "))?
+    } else {
+        game::GameState::from_fen(fen::START_FEN)?
+    };
+    let start = time::Instant::now();
+    let nodes = if divide {
+        let counts = perft::divide(&mut game, depth);
+        for (mv, nodes) in counts.iter() {
+            println!("{}: {}", game.to_uci(*mv), nodes);
+        }
+        counts.iter().map(|(_, nodes)| nodes).sum()
+    } else {
+        perft::perft(&mut game, depth)
     };
+    println!("Nodes: {} ({:.3}s)", nodes, start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+pub fn serde_test() {
+    let game = game::GameState::new(variant::VariantKind::Standard);
     let json_board = serde_json::to_string(&game).unwrap();
     println!("{}", json_board);
     // remove 11 .. 31 (first field)
@@ -62,34 +121,28 @@ pub fn tui_test() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 pub fn autoplay() -> Result<(), Box<dyn std::error::Error>> {
-    let board = game::BoardState::new();
-
     /*
     board_view::show_board(board, ms(300));
-    board.fields[25] = Some((game::PieceType::Bishop, game::Player::White));
+    board.set_field(25, Some((game::PieceType::Bishop, game::Player::White)));
     board_view::show_board(board, ms(300));
     */
 
     loop {
-        let mut game = game::GameState {
-            board,
-            ply: 0,
-            fifty_move_rule_last_event: 0,
-        };
+        let mut game = game::GameState::new(variant::VariantKind::Standard);
 
         let mut ctui = ui::CTui::new()?;
         let game_result = loop {
-            let minimax_res = minimax::minimax(game, 3, &minimax::weighted_piececount);
-            let alphabeta_res = minimax::alphabeta_init(game, 3, &minimax::weighted_piececount);
+            let minimax_res = minimax::minimax(&mut game, 3, &minimax::weighted_piececount);
+            let alphabeta_res = minimax::alphabeta_init(&game, 3, &minimax::weighted_piececount);
             assert_eq!(alphabeta_res.0, minimax_res.0);
             let minimax_num_actions = minimax_res.1.len();
             let alphabeta_num_actions = alphabeta_res.1.len();
             //assert_eq!(alphabeta_num_actions, minimax_num_actions);
             //assert_eq!(minimax_res.1, alphabeta_res.1);
-            let new_states = minimax_res.1;
+            let moves = minimax_res.1;
             let minimax_nodes = minimax_res.2;
-            if new_states.len() > 0 {
-                game = util::choose(new_states).unwrap();
+            if !moves.is_empty() && !game.fivefold_repetition() {
+                game.make_move(util::choose(moves).unwrap());
                 ctui.terminal().draw(|f| {
                     let size = ui::center(f.size(), 30, 12);
                     let chunks = ui::layout_vertical(
@@ -116,22 +169,13 @@ pub fn autoplay() -> Result<(), Box<dyn std::error::Error>> {
                     f.render_widget(Paragraph::new(t3), chunks[3]);
                     f.render_widget(Paragraph::new(t4), chunks[4]);
                 })?;
-                if ui::show_abortable(&mut ctui, ms(0)) == true {
+                if ui::show_abortable(&mut ctui, ms(0)) {
                     return Ok(());
                 }
             } else {
-                if game.fifty_move_rule_draw() {
-                    break "DRAW: 75 moves without event.".to_string();
-                }
-                let new_states = game.get_legal_moves();
-                if new_states.len() == 0 {
-                    if game.board.king_in_check(game.turn()) {
-                        break format!("{:?} WIN.", game.turn().opponent());
-                    } else {
-                        break format!("DRAW: {:?} can not move.", game.turn());
-                    }
-                } else {
-                    panic!("No possible actions and no final game state reached.");
+                match result::GameResult::from_position(&game) {
+                    Some(result) => break result.to_string(),
+                    None => return Err(error::ChaiError::NoLegalMoves.into()),
                 }
             }
         };
@@ -158,58 +202,110 @@ pub fn autoplay() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-pub fn play_as(human: game::Player) -> Result<(), Box<dyn std::error::Error>> {
-    let board = game::BoardState::new();
-
-    let mut game = game::GameState {
-        board,
-        ply: 0,
-        fifty_move_rule_last_event: 0,
-    };
+pub fn play_as(
+    human: game::Player,
+    variant: variant::VariantKind,
+    control: Option<clock::TimeControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let engine = human.opponent();
+    let mut record = game_record::Game::new(game::GameState::new(variant));
+    let mut clock = control.map(|control| clock::Clock::new(control, record.state().turn()));
+    let mut turn_start = time::Instant::now();
+    // Shown below the board until the next move.
+    let mut notice: Option<&str> = None;
 
     let mut ctui = ui::CTui::new()?;
     let game_result = loop {
-        if game.get_legal_moves().len() > 0 {
-            // TODO fifty move rules draw??
-            game = if game.turn() == human {
-                match get_new_state_from_user(&mut ctui, game)? {
-                    Some(g) => g,
-                    None => return Ok(()),
+        if let Some(result) = record.result() {
+            break result.to_string();
+        }
+        let game = record.state().clone();
+        let running_clock = clock.as_ref().map(|clock| (clock, turn_start));
+        let mv = if game.turn() == human {
+            // Undo and redo go back to the human's turn, the engine would
+            // just play again otherwise. There are no takebacks in games
+            // with a clock.
+            match get_move_from_user(&mut ctui, &game, running_clock, notice)? {
+                UserInput::Move(mv) => mv,
+                UserInput::Undo | UserInput::Redo if clock.is_some() => continue,
+                UserInput::Undo => {
+                    while record.undo() && record.state().turn() != human {}
+                    continue;
                 }
-            } else {
-                ctui.terminal().draw(|f| {
-                    let size = ui::center(f.size(), 24, 8);
-                    let chunks = ui::layout_vertical(size, [Constraint::Length(8)].as_ref());
-                    let board = board_view::BoardView::new(game.board);
-                    f.render_widget(board, chunks[0]);
-                })?;
-                let start = time::Instant::now();
-                let alphabeta_res = minimax::alphabeta_init(game, 3, &minimax::weighted_piececount);
-                let new_states = alphabeta_res.1;
-                while start.elapsed() < ms(1000) {
-                    std::thread::sleep(ms(100));
+                UserInput::Redo => {
+                    while record.redo() && record.state().turn() != human {}
+                    continue;
                 }
-                util::choose(new_states).unwrap()
-            };
+                UserInput::OfferDraw => {
+                    record.offer_draw(human)?;
+                    if minimax::accepts_draw(&game, engine, 3, &minimax::weighted_piececount) {
+                        record.accept_draw(engine)?;
+                    } else {
+                        record.decline_draw(engine)?;
+                        notice = Some("Draw declined.");
+                    }
+                    continue;
+                }
+                UserInput::ClaimDraw => {
+                    if record.claim_draw().is_err() {
+                        notice = Some("No draw to claim.");
+                    }
+                    continue;
+                }
+                UserInput::Resign => {
+                    record.resign(human)?;
+                    continue;
+                }
+                UserInput::Timeout => {
+                    if let Some(clock) = clock.as_mut() {
+                        clock.check_flag(turn_start.elapsed().as_millis() as clock::Millis);
+                    }
+                    record.flag(human)?;
+                    continue;
+                }
+                UserInput::Quit => return save_game(&record),
+            }
         } else {
-            if game.fifty_move_rule_draw() {
-                break "DRAW: 75 moves without event.".to_string();
+            let status = clock_text(running_clock).into_iter().collect();
+            draw_board(&mut ctui, game.board, HashSet::new(), status)?;
+            // The engine takes the draws it would accept.
+            if game.draw_claim().is_some()
+                && minimax::accepts_draw(&game, engine, 3, &minimax::weighted_piececount)
+            {
+                record.claim_draw()?;
+                continue;
             }
-            let new_states = game.get_legal_moves();
-            if new_states.len() == 0 {
-                if game.board.king_in_check(game.turn()) {
-                    break format!("{:?} WIN.", game.turn().opponent());
-                } else {
-                    break format!("DRAW: {:?} can not move.", game.turn());
+            let mv = minimax::choose_move(&game, 3, &minimax::weighted_piececount)?;
+            // Only the search counts against the clock, the pause is for
+            // the human to see the move coming.
+            let thinking = turn_start.elapsed();
+            if let Some(clock) = clock.as_mut() {
+                if clock.check_flag(thinking.as_millis() as clock::Millis) {
+                    record.flag(engine)?;
+                    continue;
                 }
-            } else {
-                panic!("No possible actions and no final game state reached.");
+            }
+            while turn_start.elapsed() < ms(1000) {
+                std::thread::sleep(ms(100));
+            }
+            // The clock is pressed as if the move came right after the search.
+            turn_start += turn_start.elapsed() - thinking;
+            mv
+        };
+        if let Some(clock) = clock.as_mut() {
+            if !clock.press(turn_start.elapsed().as_millis() as clock::Millis) {
+                record.flag(game.turn())?;
+                continue;
             }
         }
+        record.play(mv)?;
+        turn_start = time::Instant::now();
+        notice = None;
     };
+    let game = record.state();
     let game_result2 = game_result.clone();
     ctui.terminal().draw(|f| {
-        let size = ui::center(f.size(), 30, 9);
+        let size = ui::center(f.size(), 40, 9);
         let chunks = ui::layout_vertical(
             size,
             [Constraint::Length(8), Constraint::Length(1)].as_ref(),
@@ -219,62 +315,142 @@ pub fn play_as(human: game::Player) -> Result<(), Box<dyn std::error::Error>> {
         f.render_widget(Paragraph::new(game_result2), chunks[1]);
     })?;
     ui::show_abortable(&mut ctui, ms(30000));
+    save_game(&record)
+}
+
+// Both clocks while the player to move has been thinking since the start.
+fn clock_text(running_clock: Option<(&clock::Clock, time::Instant)>) -> Option<String> {
+    let (clock, start) = running_clock?;
+    let display = clock.display(start.elapsed().as_millis() as clock::Millis);
+    Some(format!(
+        "White {}  Black {}",
+        clock::format_clock(display[game::Player::White as usize]),
+        clock::format_clock(display[game::Player::Black as usize])
+    ))
+}
+
+// Draws the board with status lines below. Returns where the board is, for
+// mouse clicks.
+fn draw_board(
+    ctui: &mut ui::CTui,
+    board: game::BoardState,
+    highlights: HashSet<Square>,
+    status: Vec<String>,
+) -> Result<tui::layout::Rect, Box<dyn std::error::Error>> {
+    let mut board_pos = tui::layout::Rect::new(0, 0, 24, 8);
+    ctui.terminal().draw(|f| {
+        let size = ui::center(f.size(), 24, 8 + status.len() as u16);
+        let mut constraints = vec![Constraint::Length(8)];
+        constraints.extend(status.iter().map(|_| Constraint::Length(1)));
+        let chunks = ui::layout_vertical(size, constraints);
+        board_pos = chunks[0];
+        f.render_widget(board_view::BoardView::newh(board, highlights), board_pos);
+        for (line, &chunk) in status.into_iter().zip(chunks[1..].iter()) {
+            f.render_widget(Paragraph::new(line), chunk);
+        }
+    })?;
+    Ok(board_pos)
+}
+
+// Appends the game to chai.pgn in the working directory, with the moves
+// which were taken back as variations.
+fn save_game(record: &game_record::Game) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pgn = record.to_pgn();
+    pgn.set_tag("Event", "Chai TUI game");
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open("chai.pgn")?;
+    file.write_all(pgn::write_pgn(&pgn)?.as_bytes())?;
     Ok(())
 }
 
-fn get_new_state_from_user(
+enum UserInput {
+    Move(game::Move),
+    Undo,
+    Redo,
+    OfferDraw,
+    ClaimDraw,
+    Resign,
+    Timeout,
+    Quit,
+}
+
+// With a clock, the clock of the player to move runs since the given start
+// and is shown below the board.
+fn get_move_from_user(
     ctui: &mut ui::CTui,
-    game: game::GameState,
-) -> Result<Option<game::GameState>, Box<dyn std::error::Error>> {
-    let mut selected_field: Option<usize> = None;
+    game: &game::GameState,
+    running_clock: Option<(&clock::Clock, time::Instant)>,
+    notice: Option<&str>,
+) -> Result<UserInput, Box<dyn std::error::Error>> {
+    let mut selected_field: Option<Square> = None;
+    // Crazyhouse: a piece from the pocket, selected by its letter
+    let mut selected_drop: Option<game::PieceType> = None;
     loop {
-        let (valid_targets, highlights, new_pos_and_states) = if let Some(field) = selected_field {
-            let mut targets = HashSet::new();
-            let piece = game.board.fields[field].unwrap().0;
-            let new_pos_and_states = game.get_legal_moves_for_single_piece(piece, field);
-            for &(new_pos, _) in &new_pos_and_states {
-                targets.insert(new_pos);
-            }
-            let mut h = targets.clone();
+        let (highlights, moves) = if let Some(piece) = selected_drop {
+            let mut moves = game.get_legal_moves();
+            moves.retain(|mv| mv.drop == Some(piece));
+            (moves.iter().map(|mv| Square::at(mv.to)).collect(), moves)
+        } else if let Some(field) = selected_field {
+            let moves = game.get_legal_moves_for_single_piece(field.index());
+            let mut h: HashSet<Square> = moves.iter().map(|mv| Square::at(mv.to)).collect();
             h.insert(field);
-            (targets, h, new_pos_and_states)
+            (h, moves)
         } else {
-            (HashSet::new(), HashSet::new(), Vec::new())
+            (HashSet::new(), Vec::new())
         };
-        let mut board_pos = tui::layout::Rect::new(0, 0, 24, 8);
-        ctui.terminal().draw(|f| {
-            let size = ui::center(f.size(), 24, 8);
-            let chunks = ui::layout_vertical(size, [Constraint::Length(8)].as_ref());
-            let board = board_view::BoardView::newh(game.board, highlights);
-            board_pos = chunks[0];
-            f.render_widget(board, board_pos);
-        })?;
+        let status = clock_text(running_clock)
+            .into_iter()
+            .chain(notice.map(str::to_string))
+            .collect();
+        let board_pos = draw_board(ctui, game.board, highlights, status)?;
         loop {
+            if let Some((clock, start)) = running_clock {
+                // Redraw the clock every now and then.
+                if !event::poll(ms(100))? {
+                    if clock.clone().check_flag(start.elapsed().as_millis() as clock::Millis) {
+                        return Ok(UserInput::Timeout);
+                    }
+                    break;
+                }
+            }
             match event::read()? {
                 event::Event::Key(key_event) => {
-                    if key_event.code == event::KeyCode::Char('q') {
-                        return Ok(None);
+                    let drop = match key_event.code {
+                        event::KeyCode::Char('q') => return Ok(UserInput::Quit),
+                        event::KeyCode::Char('u') => return Ok(UserInput::Undo),
+                        event::KeyCode::Char('r') => return Ok(UserInput::Redo),
+                        event::KeyCode::Char('d') => return Ok(UserInput::OfferDraw),
+                        event::KeyCode::Char('c') => return Ok(UserInput::ClaimDraw),
+                        event::KeyCode::Char('x') => return Ok(UserInput::Resign),
+                        event::KeyCode::Char('Q') => Some(game::PieceType::Queen),
+                        event::KeyCode::Char('R') => Some(game::PieceType::Rook),
+                        event::KeyCode::Char('B') => Some(game::PieceType::Bishop),
+                        event::KeyCode::Char('N') => Some(game::PieceType::Knight),
+                        event::KeyCode::Char('P') => Some(game::PieceType::Pawn),
+                        _ => None,
+                    };
+                    let pocket = game.pockets[game.turn() as usize];
+                    if let Some(piece) = drop.filter(|&piece| pocket.count(piece) > 0) {
+                        selected_drop = Some(piece);
+                        selected_field = None;
+                        break;
                     }
                 }
                 event::Event::Mouse(mouse_event) => {
                     if mouse_event.kind == event::MouseEventKind::Down(event::MouseButton::Left) {
                         selected_field = None;
-                        if let Some(clicked_field) = board_view::BoardView::get_field_index_from_pos(
+                        selected_drop = None;
+                        if let Some(clicked_field) = board_view::BoardView::get_square_from_pos(
                             board_pos,
                             mouse_event.column,
                             mouse_event.row,
                         ) {
-                            if valid_targets.contains(&clicked_field) { // TODO seems like this can be omitted using the conditions below
-                                let new_states: Vec<game::GameState> = new_pos_and_states.into_iter().filter(|&(pos, _state)| pos==clicked_field).map(|(_pos, state)| state).collect();
-                                if new_states.len() == 1 {
-                                    return Ok(Some(new_states[0]));
-                                } else if new_states.len() > 1 {
-                                    // TODO promotions?
-                                    return Ok(Some(new_states[0]));
-                                } else {
-                                    panic!("Internal error: Field was clickable but no new state found.");
-                                }
-                            } else if let Some(piece) = game.board.fields[clicked_field] {
+                            // TODO promotions? The first one is the queen.
+                            if let Some(&mv) = moves.iter().find(|mv| mv.to == clicked_field.index()) {
+                                return Ok(UserInput::Move(mv));
+                            } else if let Some(piece) = game.board.field(clicked_field.index()) {
                                 if piece.1 == game.turn() {
                                     selected_field = Some(clicked_field);
                                     break;
@@ -14,44 +14,58 @@ function minimax(node, depth, maximizingPlayer) is
         return value
 */
 
+use crate::crazyhouse::DROP_PIECES;
+use crate::error::ChaiError;
 use crate::game::*;
+use crate::util;
+
+// The best possible value for the winner, the rules are up to the variant.
+fn finished_value(game_state: &GameState, finished: FinishedState) -> i32 {
+    match game_state.variant.rules().winner(game_state, finished) {
+        Some(Player::White) => i32::MAX,
+        Some(Player::Black) => i32::MIN,
+        None => 0,
+    }
+}
 
 // Returns tuple (best value, best actions, number of nodes evaluated)
 pub fn minimax(
-    game_state: GameState,
+    game_state: &mut GameState,
     depth: usize,
-    heuristic: &impl Fn(GameState) -> i32,
-) -> (i32, Vec<GameState>, u64) {
+    heuristic: &impl Fn(&GameState) -> i32,
+) -> (i32, Vec<Move>, u64) {
+    let rules = game_state.variant.rules();
+    if let Some(finished) = rules.game_end(game_state) {
+        return (finished_value(game_state, finished), vec![], 1);
+    }
     if depth == 0 {
         return (heuristic(game_state), vec![], 1);
     }
-    if game_state.fifty_move_rule_draw() {
-        return (0, vec![], 1);
-    }
-    let new_states = game_state.get_legal_moves();
-    if new_states.len() == 0 {
-        if game_state.board.king_in_check(game_state.turn()) {
-            match game_state.turn() {
-                Player::White => return (i32::MIN, vec![], 1),
-                Player::Black => return (i32::MAX, vec![], 1),
-            }
-        } else {
-            return (0, vec![], 1);
-        }
+    let moves = game_state.get_legal_moves();
+    if moves.is_empty() {
+        let finished = rules.no_legal_moves(game_state);
+        return (finished_value(game_state, finished), vec![], 1);
     }
     let (better, fold_init_val) = match game_state.turn() {
         Player::White => (std::cmp::Ordering::Greater, i32::MIN),
         Player::Black => (std::cmp::Ordering::Less, i32::MAX),
     };
-    new_states
+    moves
         .into_iter()
-        .fold((fold_init_val, vec![], 1), |mut acc, new_state| {
-            let minimax_res = minimax(new_state, depth - 1, heuristic);
+        .fold((fold_init_val, vec![], 1), |mut acc, mv| {
+            let undo = game_state.make_move(mv);
+            // Repeating a position thrice is a draw as soon as it is claimed.
+            let minimax_res = if game_state.threefold_repetition() {
+                (0, vec![], 1)
+            } else {
+                minimax(game_state, depth - 1, heuristic)
+            };
+            game_state.unmake_move(mv, undo);
             let num_nodes_evald = acc.2 + minimax_res.2;
             if minimax_res.0.cmp(&acc.0) == better {
-                (minimax_res.0, vec![new_state], num_nodes_evald)
+                (minimax_res.0, vec![mv], num_nodes_evald)
             } else if minimax_res.0 == acc.0 {
-                acc.1.push(new_state);
+                acc.1.push(mv);
                 (acc.0, acc.1, num_nodes_evald)
             } else {
                 (acc.0, acc.1, num_nodes_evald)
@@ -94,30 +108,19 @@ instead of maximizing, we check if a new value is "better" than an old value.
 Instead of α ≥ β or β ≤ α, we just check if gamma is "better" than delta.
 */
 pub fn alphabeta(
-    game_state: GameState,
+    game_state: &mut GameState,
     depth: usize,
     mut gamma: i32,
     delta: i32,
-    heuristic: &impl Fn(GameState) -> i32,
-) -> (i32, Vec<GameState>, u64) {
+    heuristic: &impl Fn(&GameState) -> i32,
+) -> (i32, Vec<Move>, u64) {
+    let rules = game_state.variant.rules();
+    if let Some(finished) = rules.game_end(game_state) {
+        return (finished_value(game_state, finished), vec![], 1);
+    }
     if depth == 0 {
         return (heuristic(game_state), vec![], 1);
     }
-    if game_state.fifty_move_rule_draw() {
-        return (0, vec![], 1);
-    }
-    let new_states = game_state.get_legal_moves();
-    if new_states.len() == 0 {
-        if game_state.board.king_in_check(game_state.turn()) {
-            match game_state.turn() {
-                Player::White => return (i32::MIN, vec![], 1),
-                Player::Black => return (i32::MAX, vec![], 1),
-            }
-        } else {
-            return (0, vec![], 1);
-        }
-    }
-
     let (better, mut best_val) = match game_state.turn() {
         Player::White => (std::cmp::Ordering::Greater, i32::MIN),
         Player::Black => (std::cmp::Ordering::Less, i32::MAX),
@@ -126,13 +129,25 @@ pub fn alphabeta(
     let mut actions = vec![];
     let mut num_nodes = 1;
 
-    for new_state in new_states.into_iter() {
-        let van = alphabeta(new_state, depth - 1, delta, gamma, heuristic);
+    // The moves are generated lazily, most of them are never generated if
+    // a cutoff happens early.
+    let mut picker = MovePicker::new();
+    let mut any_move = false;
+    while let Some(mv) = picker.next_move(game_state) {
+        any_move = true;
+        let undo = game_state.make_move(mv);
+        // Repeating a position thrice is a draw as soon as it is claimed.
+        let van = if game_state.threefold_repetition() {
+            (0, vec![], 1)
+        } else {
+            alphabeta(game_state, depth - 1, delta, gamma, heuristic)
+        };
+        game_state.unmake_move(mv, undo);
         num_nodes += van.2;
         // maximize value
         if van.0.cmp(&best_val) == better {
             best_val = van.0;
-            actions = vec![new_state];
+            actions = vec![mv];
             // maximize alpha/beta
             if best_val.cmp(&gamma) == better {
                 gamma = best_val;
@@ -148,45 +163,83 @@ pub fn alphabeta(
                 }
             }
         } else if van.0 == best_val {
-            actions.push(new_state);
-        } else {
+            actions.push(mv);
         }
     }
+    if !any_move {
+        let finished = rules.no_legal_moves(game_state);
+        return (finished_value(game_state, finished), vec![], 1);
+    }
 
     (best_val, actions, num_nodes)
 }
 
 pub fn alphabeta_init(
-    game_state: GameState,
+    game_state: &GameState,
     depth: usize,
-    heuristic: &impl Fn(GameState) -> i32,
-) -> (i32, Vec<GameState>, u64) {
+    heuristic: &impl Fn(&GameState) -> i32,
+) -> (i32, Vec<Move>, u64) {
     let (alpha, beta) = (i32::MIN, i32::MAX);
+    // The search makes and unmakes moves on its own copy of the game state.
+    let mut game_state = game_state.clone();
     match game_state.turn() {
-        Player::White => alphabeta(game_state, depth, alpha, beta, heuristic),
-        Player::Black => alphabeta(game_state, depth, beta, alpha, heuristic),
+        Player::White => alphabeta(&mut game_state, depth, alpha, beta, heuristic),
+        Player::Black => alphabeta(&mut game_state, depth, beta, alpha, heuristic),
+    }
+}
+
+// A random one of the best moves found by alphabeta.
+pub fn choose_move(
+    game_state: &GameState,
+    depth: usize,
+    heuristic: &impl Fn(&GameState) -> i32,
+) -> Result<Move, ChaiError> {
+    let (_, moves, _) = alphabeta_init(game_state, depth, heuristic);
+    util::choose(moves).ok_or(ChaiError::NoLegalMoves)
+}
+
+// Whether the engine playing player takes a draw (offered or claimable):
+// only if the search does not find anything better for it.
+pub fn accepts_draw(
+    game_state: &GameState,
+    player: Player,
+    depth: usize,
+    heuristic: &impl Fn(&GameState) -> i32,
+) -> bool {
+    let (value, _, _) = alphabeta_init(game_state, depth, heuristic);
+    match player {
+        Player::White => value <= 0,
+        Player::Black => value >= 0,
+    }
+}
+
+pub fn piece_value(piece: PieceType) -> i32 {
+    match piece {
+        PieceType::InitKing => 0,
+        PieceType::King => 0,
+        PieceType::Queen => 90,
+        PieceType::InitRook => 50,
+        PieceType::Rook => 50,
+        PieceType::Bishop => 30,
+        PieceType::Knight => 30,
+        PieceType::InitPawn => 10,
+        PieceType::Pawn => 10,
     }
 }
 
-pub fn weighted_piececount(game: GameState) -> i32 {
+pub fn weighted_piececount(game: &GameState) -> i32 {
     let mut sum = 0;
     for (piece, player) in game.board.get_pieces() {
         let factor = match player {
             Player::White => 1,
             Player::Black => -1,
         };
-        let value = match piece {
-            PieceType::InitKing => 0,
-            PieceType::King => 0,
-            PieceType::Queen => 90,
-            PieceType::InitRook => 50,
-            PieceType::Rook => 50,
-            PieceType::Bishop => 30,
-            PieceType::Knight => 30,
-            PieceType::InitPawn => 10,
-            PieceType::Pawn => 10,
-        };
-        sum += factor * value;
+        sum += factor * piece_value(piece);
+    }
+    // Crazyhouse: pieces in the pocket are worth as much as on the board.
+    for &piece in DROP_PIECES.iter() {
+        let count = |player: Player| game.pockets[player as usize].count(piece) as i32;
+        sum += (count(Player::White) - count(Player::Black)) * piece_value(piece);
     }
-    sum
+    sum * game.variant.rules().material_sign()
 }
@@ -0,0 +1,152 @@
+// Performance test (perft): counts the leaf nodes of the tree of legal moves
+// up to a fixed depth. Comparing the counts against well-known reference
+// values is the standard way to validate a move generator, divide() helps
+// narrowing a mismatch down to a single move.
+
+use crate::game::*;
+
+pub fn perft(game_state: &mut GameState, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = game_state.get_legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for mv in moves {
+        let undo = game_state.make_move(mv);
+        nodes += perft(game_state, depth - 1);
+        game_state.unmake_move(mv, undo);
+    }
+    nodes
+}
+
+// The perft node count below each legal move.
+pub fn divide(game_state: &mut GameState, depth: usize) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return vec![];
+    }
+    let mut counts = vec![];
+    for mv in game_state.get_legal_moves() {
+        let undo = game_state.make_move(mv);
+        counts.push((mv, perft(game_state, depth - 1)));
+        game_state.unmake_move(mv, undo);
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::START_FEN;
+    use crate::variant::VariantKind;
+
+    // Reference values from https://www.chessprogramming.org/Perft_Results
+    fn check(fen: &str, expected: &[u64]) {
+        check_variant(VariantKind::Standard, fen, expected);
+    }
+
+    fn check_variant(variant: VariantKind, fen: &str, expected: &[u64]) {
+        let mut game_state = GameState::from_fen_with_variant(fen, variant).unwrap();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            assert_eq!(perft(&mut game_state, depth + 1), nodes, "{} at depth {}", fen, depth + 1);
+        }
+        assert_eq!(game_state.to_fen(), fen);
+    }
+
+    #[test]
+    fn start_position() {
+        check(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8902, 197281],
+        );
+    }
+
+    #[test]
+    fn kiwipete() {
+        check(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn en_passant_and_pins() {
+        check("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn castling_and_promotion() {
+        check(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        check(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn underpromotion() {
+        check("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", &[24, 496, 9483]);
+    }
+
+    #[test]
+    fn middlegame() {
+        check(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890],
+        );
+    }
+
+    // https://www.chessprogramming.org/Chess960_Perft_Results, castling
+    // rights written in X-FEN instead of Shredder-FEN.
+    #[test]
+    fn chess960() {
+        check(
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+            &[21, 528, 12189],
+        );
+        check(
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w KQ - 1 9",
+            &[20, 479, 10471],
+        );
+        check(
+            "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w kq - 0 9",
+            &[22, 593, 13440],
+        );
+    }
+
+    #[test]
+    fn atomic() {
+        check_variant(VariantKind::Atomic, START_FEN, &[20, 400, 8902, 197326]);
+    }
+
+    #[test]
+    fn antichess() {
+        check_variant(
+            VariantKind::Antichess,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            &[20, 400, 8067, 153299],
+        );
+    }
+
+    #[test]
+    fn divide_sums_up_to_perft() {
+        let mut game_state = GameState::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        let counts = divide(&mut game_state, 2);
+        assert_eq!(counts.len(), 48);
+        assert_eq!(counts.iter().map(|(_, nodes)| nodes).sum::<u64>(), 2039);
+        let castling: Vec<u64> = counts
+            .iter()
+            .filter(|(mv, _)| mv.castling)
+            .map(|&(_, nodes)| nodes)
+            .collect();
+        assert_eq!(castling, vec![43, 43]);
+    }
+}
@@ -0,0 +1,531 @@
+// Portable Game Notation (PGN) reader and writer.
+//
+// A PGN file contains any number of games, each consisting of tag pairs like
+// [White "Tobbe"] and movetext in SAN. The movetext may contain comments,
+// numeric annotation glyphs (NAGs) and nested variations which are kept in
+// the PgnGame so that a game can be written back without losing them.
+
+use std::fmt;
+
+use crate::fen::{FenError, START_FEN};
+use crate::game::*;
+use crate::san::SanError;
+use crate::variant::VariantKind;
+
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+#[derive(Debug, Clone)]
+pub enum PgnError {
+    InvalidTag(String),
+    UnterminatedComment,
+    UnbalancedVariation,
+    UnexpectedToken(String),
+    IllegalMove { san: String, error: SanError },
+    InvalidFen(FenError),
+    UnknownVariant(String),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::InvalidTag(tag) => write!(f, "invalid tag pair '{}'", tag),
+            PgnError::UnterminatedComment => write!(f, "comment is not terminated"),
+            PgnError::UnbalancedVariation => write!(f, "parentheses of variations do not match"),
+            PgnError::UnexpectedToken(token) => write!(f, "unexpected '{}' in movetext", token),
+            PgnError::IllegalMove { san, error } => write!(f, "move '{}': {}", san, error),
+            PgnError::InvalidFen(error) => write!(f, "invalid FEN tag: {}", error),
+            PgnError::UnknownVariant(variant) => write!(f, "unknown variant '{}'", variant),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl From<FenError> for PgnError {
+    fn from(error: FenError) -> Self {
+        PgnError::InvalidFen(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PgnResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unknown,
+}
+
+impl PgnResult {
+    fn from_token(token: &str) -> Option<PgnResult> {
+        match token {
+            "1-0" => Some(PgnResult::WhiteWins),
+            "0-1" => Some(PgnResult::BlackWins),
+            "1/2-1/2" => Some(PgnResult::Draw),
+            "*" => Some(PgnResult::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PgnResult::WhiteWins => "1-0",
+            PgnResult::BlackWins => "0-1",
+            PgnResult::Draw => "1/2-1/2",
+            PgnResult::Unknown => "*",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnMove {
+    pub mv: Move,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    // Alternatives to this move, each starting in the position before it.
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+impl PgnMove {
+    pub fn new(mv: Move) -> Self {
+        PgnMove {
+            mv,
+            nags: Vec::new(),
+            comment: None,
+            variations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub comment: Option<String>, // comment before the first move
+    pub moves: Vec<PgnMove>,
+    pub result: PgnResult,
+}
+
+impl PgnGame {
+    // Creates a record for moves played from the given state, e.g. in the
+    // TUI. The Seven Tag Roster is filled with placeholders.
+    pub fn from_moves(initial_state: &GameState, moves: &[Move], result: PgnResult) -> Self {
+        let mut tags: Vec<(String, String)> = SEVEN_TAG_ROSTER
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let fen = initial_state.to_fen();
+        if initial_state.variant != VariantKind::Standard {
+            tags.push(("Variant".to_string(), initial_state.variant.name().to_string()));
+        }
+        if fen != START_FEN {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+        let mut game = PgnGame {
+            tags,
+            comment: None,
+            moves: moves.iter().map(|&mv| PgnMove::new(mv)).collect(),
+            result,
+        };
+        game.set_tag("Result", result.as_str());
+        game
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn initial_state(&self) -> Result<GameState, PgnError> {
+        let fen = self.tag("FEN").unwrap_or(START_FEN);
+        let variant = match self.tag("Variant") {
+            Some(name) => VariantKind::from_name(name)
+                .ok_or_else(|| PgnError::UnknownVariant(name.to_string()))?,
+            None => VariantKind::Standard,
+        };
+        Ok(GameState::from_fen_with_variant(fen, variant)?)
+    }
+
+    pub fn mainline(&self) -> Vec<Move> {
+        self.moves.iter().map(|pgn_move| pgn_move.mv).collect()
+    }
+
+    // Replays the mainline and returns the final state.
+    pub fn final_state(&self) -> Result<GameState, PgnError> {
+        let mut state = self.initial_state()?;
+        for mv in self.mainline() {
+            state.make_move(mv);
+        }
+        Ok(state)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    VariationStart,
+    VariationEnd,
+    Result(PgnResult),
+    San(String),
+}
+
+fn parse_tag(tag: &str) -> Result<Token, PgnError> {
+    let invalid = || PgnError::InvalidTag(tag.to_string());
+    let inner = tag.trim();
+    let space = inner.find(char::is_whitespace).ok_or_else(invalid)?;
+    let (name, value) = inner.split_at(space);
+    let value = value.trim();
+    if name.is_empty() || value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(invalid());
+    }
+    let value = value[1..value.len() - 1]
+        .replace("\\\"", "\"")
+        .replace("\\\\", "\\");
+    Ok(Token::Tag(name.to_string(), value))
+}
+
+// Traditional suffix annotations and their NAG equivalents.
+fn suffix_nag(suffix: &str) -> Option<u8> {
+    match suffix {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, PgnError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+    while let Some(c) = chars.next() {
+        match c {
+            // Escape mechanism: lines starting with % are ignored.
+            '%' if line_start => {
+                chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+                continue;
+            }
+            '\n' => {
+                line_start = true;
+                continue;
+            }
+            c if c.is_whitespace() => {}
+            '[' => {
+                // The value may contain ']', the tag only ends outside of it.
+                let mut tag = String::new();
+                let mut in_value = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        ']' if !in_value => break,
+                        '"' => in_value = !in_value,
+                        '\\' if in_value => {
+                            tag.push(c);
+                            tag.extend(chars.next());
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    tag.push(c);
+                }
+                tokens.push(parse_tag(&tag)?);
+            }
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err(PgnError::UnterminatedComment),
+                    }
+                }
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            ';' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+                line_start = true;
+                continue;
+            }
+            '(' => tokens.push(Token::VariationStart),
+            ')' => tokens.push(Token::VariationEnd),
+            '$' => {
+                let mut number = String::new();
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    number.push(d);
+                    chars.next();
+                }
+                let nag = number
+                    .parse()
+                    .map_err(|_| PgnError::UnexpectedToken(format!("${}", number)))?;
+                tokens.push(Token::Nag(nag));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|&&c| !c.is_whitespace() && !"[]{}();$".contains(c))
+                {
+                    word.push(c);
+                    chars.next();
+                }
+                tokenize_word(&word, &mut tokens)?;
+            }
+        }
+        line_start = false;
+    }
+    Ok(tokens)
+}
+
+// Splits words like "12.", "12...Nf6", "e4!?" or "1-0" into tokens.
+fn tokenize_word(word: &str, tokens: &mut Vec<Token>) -> Result<(), PgnError> {
+    if let Some(result) = PgnResult::from_token(word) {
+        tokens.push(Token::Result(result));
+        return Ok(());
+    }
+    let without_number = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    let word = if without_number.starts_with('.') {
+        without_number.trim_start_matches('.')
+    } else {
+        word
+    };
+    if word.is_empty() {
+        return Ok(());
+    }
+    let san = word.trim_end_matches(['!', '?']);
+    if san.is_empty() {
+        return Err(PgnError::UnexpectedToken(word.to_string()));
+    }
+    tokens.push(Token::San(san.to_string()));
+    let suffix = &word[san.len()..];
+    if !suffix.is_empty() {
+        let nag = suffix_nag(suffix).ok_or_else(|| PgnError::UnexpectedToken(word.to_string()))?;
+        tokens.push(Token::Nag(nag));
+    }
+    Ok(())
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn parse_game(&mut self) -> Result<PgnGame, PgnError> {
+        let mut game = PgnGame {
+            tags: Vec::new(),
+            comment: None,
+            moves: Vec::new(),
+            result: PgnResult::Unknown,
+        };
+        while let Some(Token::Tag(name, value)) = self.peek() {
+            game.tags.push((name.clone(), value.clone()));
+            self.index += 1;
+        }
+        let initial_state = game.initial_state()?;
+        let (comment, moves) = self.parse_movetext(initial_state, false)?;
+        game.comment = comment;
+        game.moves = moves;
+        if let Some(&Token::Result(result)) = self.peek() {
+            game.result = result;
+            self.index += 1;
+        }
+        Ok(game)
+    }
+
+    // Parses moves, starting in the given state, until the end of the game or
+    // the end of the variation. Returns the comment in front of the first move
+    // and the moves.
+    fn parse_movetext(
+        &mut self,
+        mut state: GameState,
+        in_variation: bool,
+    ) -> Result<(Option<String>, Vec<PgnMove>), PgnError> {
+        let mut comment = None;
+        let mut moves: Vec<PgnMove> = Vec::new();
+        // The state before the last move, variations start from here.
+        let mut previous_state = state.clone();
+        loop {
+            let token = match self.peek() {
+                Some(token) => token.clone(),
+                None if in_variation => return Err(PgnError::UnbalancedVariation),
+                None => break,
+            };
+            match token {
+                Token::Tag(..) | Token::Result(_) if !in_variation => break,
+                Token::Tag(name, _) => return Err(PgnError::UnexpectedToken(name)),
+                Token::Result(result) => {
+                    return Err(PgnError::UnexpectedToken(result.as_str().to_string()))
+                }
+                Token::San(san) => {
+                    let mv = state
+                        .parse_san(&san)
+                        .map_err(|error| PgnError::IllegalMove { san, error })?;
+                    previous_state = state.clone();
+                    state.make_move(mv);
+                    moves.push(PgnMove::new(mv));
+                }
+                Token::Comment(text) => {
+                    let target = match moves.last_mut() {
+                        Some(last) => &mut last.comment,
+                        None => &mut comment,
+                    };
+                    match target {
+                        Some(existing) => {
+                            existing.push(' ');
+                            existing.push_str(&text);
+                        }
+                        None => *target = Some(text),
+                    }
+                }
+                Token::Nag(nag) => match moves.last_mut() {
+                    Some(last) => last.nags.push(nag),
+                    None => return Err(PgnError::UnexpectedToken(format!("${}", nag))),
+                },
+                Token::VariationStart => {
+                    self.index += 1;
+                    let (_, variation) = self.parse_movetext(previous_state.clone(), true)?;
+                    match moves.last_mut() {
+                        Some(last) => last.variations.push(variation),
+                        None => return Err(PgnError::UnexpectedToken("(".to_string())),
+                    }
+                    continue; // the closing parenthesis was consumed already
+                }
+                Token::VariationEnd if in_variation => {
+                    self.index += 1;
+                    break;
+                }
+                Token::VariationEnd => return Err(PgnError::UnbalancedVariation),
+            }
+            self.index += 1;
+        }
+        Ok((comment, moves))
+    }
+}
+
+// Reads all games from a PGN text.
+pub fn read_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        index: 0,
+    };
+    let mut games = Vec::new();
+    while parser.peek().is_some() {
+        games.push(parser.parse_game()?);
+    }
+    Ok(games)
+}
+
+fn escape_tag_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_moves(state: &GameState, moves: &[PgnMove], words: &mut Vec<String>) {
+    let mut state = state.clone();
+    // The move number has to be repeated for black after interruptions like
+    // comments or variations.
+    let mut needs_number = true;
+    for pgn_move in moves {
+        let number = state.ply / 2 + 1;
+        let san = state.to_san(pgn_move.mv);
+        // Move numbers are kept on the same line as their move.
+        match state.turn() {
+            Player::White => words.push(format!("{}. {}", number, san)),
+            Player::Black if needs_number => words.push(format!("{}... {}", number, san)),
+            Player::Black => words.push(san),
+        }
+        words.extend(pgn_move.nags.iter().map(|nag| format!("${}", nag)));
+        needs_number = false;
+        if let Some(comment) = &pgn_move.comment {
+            words.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        for variation in &pgn_move.variations {
+            words.push("(".to_string());
+            write_moves(&state, variation, words);
+            words.push(")".to_string());
+            needs_number = true;
+        }
+        state.make_move(pgn_move.mv);
+    }
+}
+
+// Writes a game in export format: the Seven Tag Roster in its prescribed
+// order, followed by all other tags and the movetext wrapped at 80 columns.
+pub fn write_pgn(game: &PgnGame) -> Result<String, PgnError> {
+    let mut pgn = String::new();
+    for &(name, default) in SEVEN_TAG_ROSTER.iter() {
+        let value = match name {
+            "Result" => game.result.as_str(),
+            _ => game.tag(name).unwrap_or(default),
+        };
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag_value(value)));
+    }
+    for (name, value) in &game.tags {
+        if SEVEN_TAG_ROSTER.iter().all(|&(roster_name, _)| roster_name != name) {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag_value(value)));
+        }
+    }
+    pgn.push('\n');
+
+    let mut words = Vec::new();
+    if let Some(comment) = &game.comment {
+        words.push(format!("{{{}}}", comment));
+    }
+    write_moves(&game.initial_state()?, &game.moves, &mut words);
+    words.push(game.result.as_str().to_string());
+
+    let mut line = String::new();
+    for word in words {
+        // No space after an opening and before a closing parenthesis
+        let glue = line.is_empty() || line.ends_with('(') || word == ")";
+        if !glue && line.len() + 1 + word.len() > 80 {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        } else if !glue {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    pgn.push_str(&line);
+    pgn.push_str("\n\n");
+    Ok(pgn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brackets_and_quotes_in_tag_values() {
+        let text = "[Event \"A [b] \\\"c\\\"\"]\n[Site \"?\"]\n\n1. e4 *\n";
+        let games = read_pgn(text).unwrap();
+        assert_eq!(games[0].tag("Event"), Some("A [b] \"c\""));
+        assert_eq!(games[0].tag("Site"), Some("?"));
+    }
+}
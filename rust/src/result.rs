@@ -0,0 +1,160 @@
+// How a game ended: the winner (None for a draw) and why. FinishedState
+// only knows the positions which end the game by themselves, the players
+// may also end it by resigning, agreeing to a draw, claiming a draw or
+// running out of time.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::game::*;
+use crate::pgn::PgnResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Termination {
+    // The position ended the game, e.g. checkmate or the 75-move rule.
+    Position(FinishedState),
+    Resignation,
+    AgreedDraw,
+    // 50 moves without a capture or pawn move, claimed by a player. After
+    // 75 moves, the game ends without a claim.
+    FiftyMoveClaim,
+    ThreefoldClaim,
+    // A draw if the opponent can not win any more.
+    TimeForfeit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GameResult {
+    pub winner: Option<Player>,
+    pub termination: Termination,
+}
+
+impl GameResult {
+    // The result if the position ends the game, a threefold repetition
+    // only ends it when it is claimed.
+    pub fn from_position(game_state: &GameState) -> Option<GameResult> {
+        match game_state.get_finished_state() {
+            FinishedState::Ongoing | FinishedState::ThreefoldRepetition => None,
+            finished => Some(GameResult {
+                winner: game_state.variant.rules().winner(game_state, finished),
+                termination: Termination::Position(finished),
+            }),
+        }
+    }
+
+    pub fn resignation(player: Player) -> GameResult {
+        GameResult {
+            winner: Some(player.opponent()),
+            termination: Termination::Resignation,
+        }
+    }
+
+    pub fn time_forfeit(game_state: &GameState, flagged: Player) -> GameResult {
+        GameResult {
+            winner: clock::flag_winner(game_state, flagged),
+            termination: Termination::TimeForfeit,
+        }
+    }
+
+    pub fn draw(termination: Termination) -> GameResult {
+        GameResult {
+            winner: None,
+            termination,
+        }
+    }
+
+    pub fn pgn_result(&self) -> PgnResult {
+        match self.winner {
+            Some(Player::White) => PgnResult::WhiteWins,
+            Some(Player::Black) => PgnResult::BlackWins,
+            None => PgnResult::Draw,
+        }
+    }
+
+    // The value of the PGN Termination tag.
+    pub fn pgn_termination(&self) -> &'static str {
+        match self.termination {
+            Termination::TimeForfeit => "time forfeit",
+            _ => "normal",
+        }
+    }
+}
+
+fn reason(finished: FinishedState) -> &'static str {
+    match finished {
+        FinishedState::Ongoing => "nothing",
+        FinishedState::Checkmate => "checkmate",
+        FinishedState::Stalemate => "stalemate",
+        FinishedState::ThreefoldRepetition => "threefold repetition",
+        FinishedState::FivefoldRepetition => "fivefold repetition",
+        FinishedState::FiftyMoveDraw => "the 75-move rule",
+        FinishedState::InsufficientMaterial => "insufficient material",
+        FinishedState::KingOfTheHill => "reaching the center",
+        FinishedState::ThreeChecks => "three checks",
+        FinishedState::KingExploded => "blowing up the king",
+        FinishedState::OutOfMoves => "running out of moves",
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.termination {
+            Termination::Position(finished) => reason(finished),
+            Termination::Resignation => "resignation",
+            Termination::AgreedDraw => "agreement",
+            Termination::FiftyMoveClaim => "the fifty-move rule",
+            Termination::ThreefoldClaim => "threefold repetition",
+            Termination::TimeForfeit if self.winner.is_none() => {
+                "timeout vs insufficient material"
+            }
+            Termination::TimeForfeit => "time forfeit",
+        };
+        match self.winner {
+            Some(winner) => write!(f, "{:?} wins by {}", winner, reason),
+            None => write!(f, "Draw by {}", reason),
+        }
+    }
+}
+
+impl GameState {
+    // 50 moves by each player without a capture or pawn move.
+    pub fn can_claim_fifty_moves(&self) -> bool {
+        self.ply - self.fifty_move_rule_last_event >= 100
+    }
+
+    // The draws the player to move may claim in this position.
+    pub fn draw_claim(&self) -> Option<GameResult> {
+        if self.threefold_repetition() {
+            Some(GameResult::draw(Termination::ThreefoldClaim))
+        } else if self.can_claim_fifty_moves() {
+            Some(GameResult::draw(Termination::FiftyMoveClaim))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_and_claims() {
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 99 80").unwrap();
+        assert_eq!(game_state.draw_claim(), None);
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 100 80").unwrap();
+        let claim = game_state.draw_claim().unwrap();
+        assert_eq!(claim.to_string(), "Draw by the fifty-move rule");
+        assert_eq!(GameResult::from_position(&game_state), None);
+
+        let game_state = GameState::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 80").unwrap();
+        let timeout = GameResult::time_forfeit(&game_state, Player::White);
+        assert_eq!(timeout.to_string(), "Draw by timeout vs insufficient material");
+        assert_eq!(timeout.pgn_result(), PgnResult::Draw);
+        let mated = GameState::from_fen("4k3/4Q3/4K3/8/8/8/8/8 b - - 0 80").unwrap();
+        let result = GameResult::from_position(&mated).unwrap();
+        assert_eq!(result.to_string(), "White wins by checkmate");
+    }
+}
@@ -0,0 +1,238 @@
+// Standard Algebraic Notation (SAN), e.g. "Nxe5+", "O-O-O" or "exd8=Q#".
+//
+// SAN only makes sense relative to a position: Disambiguation and the
+// check/mate suffixes depend on the other legal moves, so everything in here
+// is built on top of the move generator in game.rs.
+
+use std::fmt;
+
+use crate::game::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SanError {
+    Invalid(String),
+    Illegal(String),
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanError::Invalid(san) => write!(f, "'{}' is not a valid SAN move", san),
+            SanError::Illegal(san) => write!(f, "'{}' is not a legal move in this position", san),
+            SanError::Ambiguous(san) => {
+                write!(f, "'{}' matches several legal moves in this position", san)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+// The letter of a piece in SAN, pawns do not have one.
+fn piece_letter(piece: PieceType) -> Option<char> {
+    match piece {
+        PieceType::InitKing | PieceType::King => Some('K'),
+        PieceType::Queen => Some('Q'),
+        PieceType::InitRook | PieceType::Rook => Some('R'),
+        PieceType::Bishop => Some('B'),
+        PieceType::Knight => Some('N'),
+        PieceType::InitPawn | PieceType::Pawn => None,
+    }
+}
+
+fn promotion_piece(letter: char) -> Option<PieceType> {
+    match letter {
+        'Q' => Some(PieceType::Queen),
+        'R' => Some(PieceType::Rook),
+        'B' => Some(PieceType::Bishop),
+        'N' => Some(PieceType::Knight),
+        'K' => Some(PieceType::King), // Antichess
+        _ => None,
+    }
+}
+
+// Crazyhouse drops like "N@f3" name pawns, too.
+fn drop_letter(piece: PieceType) -> char {
+    piece_letter(piece).unwrap_or('P')
+}
+
+fn drop_piece(letters: &str) -> Option<PieceType> {
+    let mut chars = letters.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) | (Some('P'), None) => Some(PieceType::Pawn),
+        (Some(letter), None) => promotion_piece(letter),
+        _ => None,
+    }
+}
+
+fn file_char(pos: usize) -> char {
+    (b'a' + (pos % 8) as u8) as char
+}
+
+fn rank_char(pos: usize) -> char {
+    (b'1' + (pos / 8) as u8) as char
+}
+
+impl GameState {
+    fn piece_letter_at(&self, pos: usize) -> Option<char> {
+        self.board.field(pos).and_then(|(piece, _)| piece_letter(piece))
+    }
+
+    // Formats a legal move of the player to move.
+    pub fn to_san(&self, mv: Move) -> String {
+        let mut san = if mv.castling {
+            if mv.is_kingside_castling() {
+                "O-O".to_string()
+            } else {
+                "O-O-O".to_string()
+            }
+        } else if let Some(piece) = mv.drop {
+            format!("{}@{}", drop_letter(piece), pos_to_algebraic(mv.to))
+        } else {
+            let mut san = String::new();
+            match self.piece_letter_at(mv.from) {
+                Some(letter) => {
+                    san.push(letter);
+                    // Disambiguate between pieces of the same kind which can
+                    // reach the same field: file first, then rank, then both.
+                    let others: Vec<usize> = self
+                        .get_legal_moves()
+                        .into_iter()
+                        .filter(|other| {
+                            other.to == mv.to
+                                && other.from != mv.from
+                                && !other.castling
+                                && self.piece_letter_at(other.from) == Some(letter)
+                        })
+                        .map(|other| other.from)
+                        .collect();
+                    if !others.is_empty() {
+                        if others.iter().all(|&other| other % 8 != mv.from % 8) {
+                            san.push(file_char(mv.from));
+                        } else if others.iter().all(|&other| other / 8 != mv.from / 8) {
+                            san.push(rank_char(mv.from));
+                        } else {
+                            san.push_str(&pos_to_algebraic(mv.from));
+                        }
+                    }
+                }
+                None => {
+                    if mv.capture {
+                        san.push(file_char(mv.from));
+                    }
+                }
+            }
+            if mv.capture {
+                san.push('x');
+            }
+            san.push_str(&pos_to_algebraic(mv.to));
+            if let Some(piece) = mv.promotion {
+                san.push('=');
+                san.push(piece_letter(piece).expect("Pawns can not be promoted to pawns."));
+            }
+            san
+        };
+        let new_state = self.after_move(mv);
+        if new_state.in_check() {
+            if new_state.get_legal_moves().is_empty() {
+                san.push('#');
+            } else {
+                san.push('+');
+            }
+        }
+        san
+    }
+
+    // Parses a SAN move into a legal move of the player to move. Check and
+    // mate suffixes as well as annotations like "!?" are accepted but not
+    // verified.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let invalid = || SanError::Invalid(san.to_string());
+        let stripped = san.trim().trim_end_matches(|c| "+#!?".contains(c));
+        let legal_moves = self.get_legal_moves();
+
+        let castling = match stripped {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(kingside) = castling {
+            return legal_moves
+                .into_iter()
+                .find(|mv| mv.castling && mv.is_kingside_castling() == kingside)
+                .ok_or_else(|| SanError::Illegal(san.to_string()));
+        }
+
+        // Pawn drops may be written without the letter, e.g. "@e4".
+        if let Some((letters, to_str)) = stripped.split_once('@') {
+            let piece = drop_piece(letters).ok_or_else(invalid)?;
+            let to = pos_from_algebraic(to_str).ok_or_else(invalid)?;
+            return legal_moves
+                .into_iter()
+                .find(|mv| mv.drop == Some(piece) && mv.to == to)
+                .ok_or_else(|| SanError::Illegal(san.to_string()));
+        }
+
+        let mut chars: Vec<char> = stripped.chars().collect();
+        let letter = match chars.first() {
+            Some(&c) if "KQRBN".contains(c) => {
+                chars.remove(0);
+                Some(c)
+            }
+            Some(_) => None,
+            None => return Err(invalid()),
+        };
+
+        let promotion = match chars.last() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+                Some(promotion_piece(c).ok_or_else(invalid)?)
+            }
+            _ => None,
+        };
+
+        if chars.len() < 2 {
+            return Err(invalid());
+        }
+        let to_str: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to = pos_from_algebraic(&to_str).ok_or_else(invalid)?;
+
+        let capture = chars.last() == Some(&'x');
+        if capture {
+            chars.pop();
+        }
+        let (mut from_file, mut from_rank) = (None, None);
+        for c in chars {
+            match c {
+                'a'..='h' if from_file.is_none() && from_rank.is_none() => {
+                    from_file = Some(c as usize - 'a' as usize)
+                }
+                '1'..='8' if from_rank.is_none() => from_rank = Some(c as usize - '1' as usize),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let candidates: Vec<Move> = legal_moves
+            .into_iter()
+            .filter(|mv| {
+                !mv.castling
+                    && mv.drop.is_none()
+                    && mv.to == to
+                    && mv.promotion == promotion
+                    && (mv.capture || !capture)
+                    && self.piece_letter_at(mv.from) == letter
+                    && from_file.is_none_or(|file| mv.from % 8 == file)
+                    && from_rank.is_none_or(|rank| mv.from / 8 == rank)
+            })
+            .collect();
+        match candidates.len() {
+            0 => Err(SanError::Illegal(san.to_string())),
+            1 => Ok(candidates[0]),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Static exchange evaluation (SEE): the material won or lost by a capture
+// once all captures on the same field have been played out. Both players
+// always capture with their least valuable piece and may stop capturing
+// whenever that is better for them. Sliders behind the captured pieces
+// (x-rays) join the exchange. Only the standard capture rules are known,
+// e.g. Atomic explosions are ignored.
+
+use crate::bitboard::{self, bit, Bitboard};
+use crate::game::*;
+use crate::minimax::piece_value;
+
+// The king may only capture last, so it is worth more than everything else.
+const KING_VALUE: i32 = 10_000;
+
+fn value(piece: PieceType) -> i32 {
+    match piece {
+        PieceType::InitKing | PieceType::King => KING_VALUE,
+        piece => piece_value(piece),
+    }
+}
+
+// The order in which the pieces join an exchange.
+const LEAST_VALUABLE_FIRST: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+impl BoardState {
+    fn least_valuable(&self, attackers: Bitboard, player: Player) -> Option<(PieceType, usize)> {
+        LEAST_VALUABLE_FIRST.iter().find_map(|&piece| {
+            let pieces = attackers & self.pieces(piece, player);
+            bitboard::fields(pieces).next().map(|pos| (piece, pos))
+        })
+    }
+
+    // The material balance of the move for the player who makes it, in the
+    // units of minimax::weighted_piececount. Quiet moves are exchanges, too:
+    // a piece moved to an attacked field may be lost.
+    pub fn see(&self, mv: Move) -> i32 {
+        let (piece, player) = match self.field(mv.from) {
+            Some(field) => field,
+            None => return 0,
+        };
+        let target = mv.to;
+        let mut occupied = self.occupied() ^ bit(mv.from);
+        let mut gain = Vec::with_capacity(32);
+        let captured = if mv.en_passant {
+            occupied ^= bit(self.en_passant_target());
+            piece_value(PieceType::Pawn)
+        } else {
+            self.field(target).map_or(0, |(captured, _)| piece_value(captured))
+        };
+        // The piece which stands on the target field and may be captured next.
+        let mut on_target = match mv.promotion {
+            Some(promoted) => {
+                gain.push(captured + piece_value(promoted) - piece_value(PieceType::Pawn));
+                value(promoted)
+            }
+            None => {
+                gain.push(captured);
+                value(piece)
+            }
+        };
+        let mut side = player.opponent();
+        while let Some((attacker, pos)) =
+            self.least_valuable(self.attackers_through(target, side, occupied) & occupied, side)
+        {
+            // Speculative: whether the capture is better than stopping is
+            // decided below.
+            let previous = *gain.last().expect("The first capture is always there.");
+            gain.push(on_target - previous);
+            occupied ^= bit(pos);
+            on_target = value(attacker);
+            side = side.opponent();
+        }
+        // Each player only continues the exchange if it gains something.
+        while gain.len() > 1 {
+            let last = gain.pop().expect("There are at least two gains.");
+            let previous = gain.last_mut().expect("There is at least one gain.");
+            *previous = -(-*previous).max(last);
+        }
+        gain[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see(fen: &str, uci: &str) -> i32 {
+        let game_state = GameState::from_fen(fen).unwrap();
+        let mv = game_state.parse_uci(uci).unwrap();
+        game_state.board.see(mv)
+    }
+
+    #[test]
+    fn exchanges() {
+        // Undefended pawn
+        assert_eq!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"), 10);
+        // Knight takes a pawn defended by a pawn
+        assert_eq!(see("1k6/8/3p4/4p3/8/5N2/8/1K6 w - - 0 1", "f3e5"), 10 - 30);
+        // Rook takes a defended pawn, the queen behind it (x-ray) recaptures
+        assert_eq!(see("1k6/4r3/8/4p3/8/8/4R3/1K2Q3 w - - 0 1", "e2e5"), 10 - 50 + 50);
+        // Moving a piece to an attacked field
+        assert_eq!(see("1k6/8/3p4/8/8/5N2/8/1K6 w - - 0 1", "f3e5"), -30);
+    }
+
+    #[test]
+    fn kings_capture_last() {
+        assert_eq!(see("4k3/8/8/8/8/8/3r4/3RK3 w - - 0 1", "e1d2"), 50);
+        // Not a legal move, but it has to be the worst case even though the
+        // rook recaptures.
+        let board = GameState::from_fen("4k3/8/8/8/8/8/3rr3/3RK3 w - - 0 1").unwrap().board;
+        let pos = |name| pos_from_algebraic(name).unwrap();
+        assert_eq!(board.see(Move::new(pos("e1"), pos("d2"), true)), 50 - KING_VALUE + 50);
+    }
+}
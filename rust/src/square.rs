@@ -0,0 +1,156 @@
+// A field of the board. The index is the same as for BoardState.fields and
+// the bitboards: 0 is a1, 7 is h1, 8 is a2 and 63 is h8. In JSON, a Square
+// is its index so that the front ends keep working with plain numbers.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bitboard::{bit, Bitboard};
+use crate::error::ChaiError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "usize", try_from = "usize")]
+pub struct Square(u8);
+
+impl Square {
+    pub fn new(index: usize) -> Option<Square> {
+        if index < 64 {
+            Some(Square(index as u8))
+        } else {
+            None
+        }
+    }
+
+    // For indices which are known to be on the board, e.g. of a Move.
+    pub fn at(index: usize) -> Square {
+        Square::new(index).expect("The index is not on the board.")
+    }
+
+    // File and rank count from 0, i.e. (0, 0) is a1 and (4, 3) is e4.
+    pub fn from_file_rank(file: usize, rank: usize) -> Option<Square> {
+        if file < 8 && rank < 8 {
+            Some(Square((rank * 8 + file) as u8))
+        } else {
+            None
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn file(self) -> usize {
+        self.index() % 8
+    }
+
+    pub fn rank(self) -> usize {
+        self.index() / 8
+    }
+
+    pub fn bit(self) -> Bitboard {
+        bit(self.index())
+    }
+
+    // a1, b1, ..., h1, a2, ..., h8
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(Square)
+    }
+
+    pub fn rank_squares(rank: usize) -> impl Iterator<Item = Square> {
+        (0..8).filter_map(move |file| Square::from_file_rank(file, rank))
+    }
+
+    pub fn file_squares(file: usize) -> impl Iterator<Item = Square> {
+        (0..8).filter_map(move |rank| Square::from_file_rank(file, rank))
+    }
+}
+
+impl From<Square> for usize {
+    fn from(square: Square) -> usize {
+        square.index()
+    }
+}
+
+impl TryFrom<usize> for Square {
+    type Error = ChaiError;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error> {
+        Square::new(index).ok_or(ChaiError::InvalidField(index))
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = (b'a' + self.file() as u8) as char;
+        let rank = (b'1' + self.rank() as u8) as char;
+        write!(f, "{}{}", file, rank)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseSquareError(pub String);
+
+impl fmt::Display for ParseSquareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not a field like e4", self.0)
+    }
+}
+
+impl std::error::Error for ParseSquareError {}
+
+impl FromStr for Square {
+    type Err = ParseSquareError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseSquareError(s.to_string());
+        let mut chars = s.chars();
+        let (file, rank) = (chars.next().ok_or_else(error)?, chars.next().ok_or_else(error)?);
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return Err(error());
+        }
+        Square::from_file_rank(file as usize - 'a' as usize, rank as usize - '1' as usize)
+            .ok_or_else(error)
+    }
+}
+
+// Serializes an Option<Square> as an index which is 0xFF for None, the way
+// the front ends knew it before there was a Square, e.g. with
+// #[serde(with = "square::serde_or_invalid")]. Every index which is not on
+// the board is read as None.
+pub mod serde_or_invalid {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Square;
+
+    pub const INVALID: usize = 0xFF;
+
+    pub fn serialize<S: Serializer>(square: &Option<Square>, serializer: S) -> Result<S::Ok, S::Error> {
+        square.map_or(INVALID, Square::index).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Square>, D::Error> {
+        Ok(Square::new(usize::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algebraic_and_json() {
+        let e4: Square = "e4".parse().unwrap();
+        assert_eq!((e4.file(), e4.rank(), e4.index()), (4, 3, 28));
+        assert_eq!(e4.to_string(), "e4");
+        assert!("i1".parse::<Square>().is_err());
+        assert!("e9".parse::<Square>().is_err());
+        assert!("e44".parse::<Square>().is_err());
+        assert_eq!(Square::all().map(|square| square.to_string()).nth(63).unwrap(), "h8");
+        assert_eq!(serde_json::to_string(&e4).unwrap(), "28");
+        assert_eq!(serde_json::from_str::<Square>("28").unwrap(), e4);
+        assert!(serde_json::from_str::<Square>("64").is_err());
+    }
+}
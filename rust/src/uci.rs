@@ -0,0 +1,114 @@
+// Long algebraic move notation as used by the UCI protocol, e.g. "e2e4" or
+// "e7e8q". Castling is written as the king's two-field move ("e1g1"). If the
+// king does not start on the e-file (Chess960), it is written as the king
+// capturing its own rook ("b1a1") like UCI_Chess960 does, because the king
+// might not move at all. Both forms are accepted when parsing.
+
+use std::fmt;
+
+use crate::game::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UciError {
+    Invalid(String),
+    Illegal(String),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::Invalid(uci) => write!(f, "'{}' is not a valid long algebraic move", uci),
+            UciError::Illegal(uci) => write!(f, "'{}' is not a legal move in this position", uci),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+fn promotion_suffix(piece: PieceType) -> Option<char> {
+    match piece {
+        PieceType::Queen => Some('q'),
+        PieceType::Rook | PieceType::InitRook => Some('r'),
+        PieceType::Bishop => Some('b'),
+        PieceType::Knight => Some('n'),
+        PieceType::King => Some('k'), // Antichess
+        _ => None,
+    }
+}
+
+fn promotion_piece(suffix: char) -> Option<PieceType> {
+    match suffix {
+        'q' => Some(PieceType::Queen),
+        'r' => Some(PieceType::Rook),
+        'b' => Some(PieceType::Bishop),
+        'n' => Some(PieceType::Knight),
+        'k' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+impl GameState {
+    pub fn to_uci(&self, mv: Move) -> String {
+        if let Some(piece) = mv.drop {
+            let letter = promotion_suffix(piece).unwrap_or('p').to_ascii_uppercase();
+            return format!("{}@{}", letter, pos_to_algebraic(mv.to));
+        }
+        let to = if mv.castling && mv.from % 8 != 4 {
+            self.castling_rook_of(mv).unwrap_or(mv.to)
+        } else {
+            mv.to
+        };
+        let mut uci = format!("{}{}", pos_to_algebraic(mv.from), pos_to_algebraic(to));
+        if let Some(suffix) = mv.promotion.and_then(promotion_suffix) {
+            uci.push(suffix);
+        }
+        uci
+    }
+
+    // Parses a long algebraic move into a legal move of the player to move.
+    pub fn parse_uci(&self, uci: &str) -> Result<Move, UciError> {
+        let invalid = || UciError::Invalid(uci.to_string());
+        let uci = uci.trim();
+        if !uci.is_ascii() || (uci.len() != 4 && uci.len() != 5) {
+            return Err(invalid());
+        }
+        // Crazyhouse drops, e.g. "N@f3"
+        if uci.as_bytes()[1] == b'@' {
+            let piece = match uci.as_bytes()[0].to_ascii_lowercase() as char {
+                'p' => PieceType::Pawn,
+                letter => promotion_piece(letter).ok_or_else(invalid)?,
+            };
+            let to = pos_from_algebraic(&uci[2..]).ok_or_else(invalid)?;
+            return self
+                .get_legal_moves()
+                .into_iter()
+                .find(|mv| mv.drop == Some(piece) && mv.to == to)
+                .ok_or_else(|| UciError::Illegal(uci.to_string()));
+        }
+        let from = pos_from_algebraic(&uci[0..2]).ok_or_else(invalid)?;
+        let to = pos_from_algebraic(&uci[2..4]).ok_or_else(invalid)?;
+        let promotion = match uci[4..].chars().next() {
+            Some(suffix) => Some(promotion_piece(suffix.to_ascii_lowercase()).ok_or_else(invalid)?),
+            None => None,
+        };
+        let legal_moves = self.get_legal_moves();
+        legal_moves
+            .iter()
+            .find(|mv| mv.from == from && mv.to == to && mv.promotion == promotion)
+            .or_else(|| {
+                legal_moves.iter().find(|&&mv| {
+                    mv.castling
+                        && mv.from == from
+                        && promotion.is_none()
+                        && self.castling_rook_of(mv) == Some(to)
+                })
+            })
+            .copied()
+            .ok_or_else(|| UciError::Illegal(uci.to_string()))
+    }
+
+    fn castling_rook_of(&self, mv: Move) -> Option<usize> {
+        self.board
+            .castling_rook(mv.from, self.turn(), mv.is_kingside_castling())
+    }
+}
@@ -1,10 +1,11 @@
 use std::time;
 use rand::seq::SliceRandom;
 
+#[allow(dead_code)] // only used by the binary
 pub fn ms(millis: u64) -> time::Duration {
     time::Duration::from_millis(millis)
 }
 
-pub fn choose<T: Copy>(s: Vec<T>) -> Option<T> {
-    s.choose(&mut rand::thread_rng()).map(|x| *x)
+pub fn choose<T: Clone>(s: Vec<T>) -> Option<T> {
+    s.choose(&mut rand::thread_rng()).cloned()
 }
@@ -0,0 +1,323 @@
+// Validation of positions which do not come from playing moves, e.g. from a
+// FEN or from JSON sent by a front end. The move generator relies on a
+// sane position (e.g. it panics if a king is missing), so such positions
+// have to be checked before anything else is done with them.
+
+use std::fmt;
+
+use crate::bitboard::{self, bit, FIRST_AND_LAST_ROW};
+use crate::game::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PositionError {
+    WrongNumberOfKings { player: Player, kings: usize },
+    PawnOnBackRank(usize),
+    OpponentInCheck,
+    TooManyPawns(Player),
+    TooManyPieces(Player),
+    TooManyPromotedPieces(Player),
+    TooManyPiecesInTotal, // pieces change sides in Crazyhouse
+    InvalidEnPassant(usize),
+    InvalidCastlingRight(usize),
+    InvalidUnmovedPawn(usize),
+    InvalidPromotedPiece(usize),
+    InconsistentCounters,
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::WrongNumberOfKings { player, kings } => {
+                write!(f, "{:?} has {} kings instead of one", player, kings)
+            }
+            PositionError::PawnOnBackRank(pos) => {
+                write!(f, "there is a pawn on {}", pos_to_algebraic(*pos))
+            }
+            PositionError::OpponentInCheck => {
+                write!(f, "the player who is not to move is in check")
+            }
+            PositionError::TooManyPawns(player) => write!(f, "{:?} has more than 8 pawns", player),
+            PositionError::TooManyPieces(player) => {
+                write!(f, "{:?} has more than 16 pieces", player)
+            }
+            PositionError::TooManyPromotedPieces(player) => write!(
+                f,
+                "{:?} has more promoted pieces than missing pawns",
+                player
+            ),
+            PositionError::TooManyPiecesInTotal => {
+                write!(f, "there are more than 32 pieces or 16 pawns")
+            }
+            PositionError::InvalidEnPassant(pos) => write!(
+                f,
+                "en passant square {} without a pawn which just double-stepped",
+                pos_to_algebraic(*pos)
+            ),
+            PositionError::InvalidCastlingRight(pos) => write!(
+                f,
+                "the piece on {} may castle but is not on its back rank",
+                pos_to_algebraic(*pos)
+            ),
+            PositionError::InvalidUnmovedPawn(pos) => write!(
+                f,
+                "the pawn on {} may double-step but is not on its initial rank",
+                pos_to_algebraic(*pos)
+            ),
+            PositionError::InvalidPromotedPiece(pos) => write!(
+                f,
+                "{} is marked as a promoted piece but there is no such piece",
+                pos_to_algebraic(*pos)
+            ),
+            PositionError::InconsistentCounters => write!(
+                f,
+                "the last capture, pawn move or double step is after the current ply"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+fn back_row(player: Player) -> usize {
+    match player {
+        Player::White => 0,
+        Player::Black => 7,
+    }
+}
+
+fn pawn_row(player: Player) -> usize {
+    match player {
+        Player::White => 1,
+        Player::Black => 6,
+    }
+}
+
+impl GameState {
+    // Collects every problem instead of stopping at the first one so that a
+    // board editor can show all of them.
+    pub fn validate(&self) -> Result<(), Vec<PositionError>> {
+        let mut errors = Vec::new();
+        self.validate_kings(&mut errors);
+        self.validate_placement(&mut errors);
+        self.validate_piece_counts(&mut errors);
+        self.validate_en_passant(&mut errors);
+        self.validate_counters(&mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_kings(&self, errors: &mut Vec<PositionError>) {
+        let rules = self.variant.rules();
+        if !rules.royal_king() {
+            return;
+        }
+        let mut kings_ok = true;
+        for &player in [Player::Black, Player::White].iter() {
+            let kings = self.board.pieces(PieceType::King, player).count_ones() as usize;
+            // Atomic chess: the last move blew up the king of the player to move.
+            let exploded = kings == 0
+                && player == self.turn()
+                && rules.game_end(self) == Some(FinishedState::KingExploded);
+            if kings != 1 && !exploded {
+                errors.push(PositionError::WrongNumberOfKings { player, kings });
+            }
+            kings_ok &= kings == 1;
+        }
+        // Checks can only be determined if both kings are there.
+        if kings_ok && rules.in_check(&self.board, self.turn().opponent()) {
+            errors.push(PositionError::OpponentInCheck);
+        }
+    }
+
+    fn validate_placement(&self, errors: &mut Vec<PositionError>) {
+        for (pos, field) in self.board.fields().iter().enumerate() {
+            let (piece, player) = match field {
+                Some(field) => *field,
+                None => continue,
+            };
+            let row = pos / 8;
+            match piece {
+                PieceType::InitPawn | PieceType::Pawn if bit(pos) & FIRST_AND_LAST_ROW != 0 => {
+                    errors.push(PositionError::PawnOnBackRank(pos))
+                }
+                PieceType::InitPawn if row != pawn_row(player) => {
+                    errors.push(PositionError::InvalidUnmovedPawn(pos))
+                }
+                PieceType::InitKing | PieceType::InitRook if row != back_row(player) => {
+                    errors.push(PositionError::InvalidCastlingRight(pos))
+                }
+                _ => {}
+            }
+        }
+        // Only queens, rooks, bishops and knights can have been pawns.
+        for pos in bitboard::fields(self.promoted) {
+            match self.board.field(pos) {
+                Some((PieceType::Queen, _))
+                | Some((PieceType::Rook, _))
+                | Some((PieceType::Bishop, _))
+                | Some((PieceType::Knight, _)) => {}
+                _ => errors.push(PositionError::InvalidPromotedPiece(pos)),
+            }
+        }
+    }
+
+    fn validate_piece_counts(&self, errors: &mut Vec<PositionError>) {
+        let count = |piece: PieceType, player: Player| {
+            self.board.pieces(piece, player).count_ones() as usize
+        };
+        if self.variant.rules().uses_pockets() {
+            // Pieces change sides, only the totals are known. Promoted pieces
+            // are pawns which went through the pocket.
+            let pieces = self.board.occupied().count_ones() as usize
+                + self.pockets.iter().map(|pocket| pocket.len()).sum::<usize>();
+            let pawns = count(PieceType::Pawn, Player::Black)
+                + count(PieceType::Pawn, Player::White)
+                + self.promoted.count_ones() as usize
+                + self.pockets.iter().map(|pocket| pocket.pawns).sum::<usize>();
+            if pieces > 32 || pawns > 16 {
+                errors.push(PositionError::TooManyPiecesInTotal);
+            }
+            return;
+        }
+        for &player in [Player::Black, Player::White].iter() {
+            let pawns = count(PieceType::Pawn, player);
+            if pawns > 8 {
+                errors.push(PositionError::TooManyPawns(player));
+            }
+            if self.board.player_pieces(player).count_ones() > 16 {
+                errors.push(PositionError::TooManyPieces(player));
+            }
+            // Every piece beyond the initial ones was a pawn before.
+            let promoted: usize = [
+                (PieceType::King, 1),
+                (PieceType::Queen, 1),
+                (PieceType::Rook, 2),
+                (PieceType::Bishop, 2),
+                (PieceType::Knight, 2),
+            ]
+            .iter()
+            .map(|&(piece, initial)| count(piece, player).saturating_sub(initial))
+            .sum();
+            if pawns <= 8 && promoted > 8 - pawns {
+                errors.push(PositionError::TooManyPromotedPieces(player));
+            }
+        }
+    }
+
+    // Both counters refer to plies which have already been played, the
+    // rules subtract them from the current ply.
+    fn validate_counters(&self, errors: &mut Vec<PositionError>) {
+        let double_step_ahead = self
+            .board
+            .en_passant_field
+            .is_some_and(|en_passant| en_passant.ply >= self.ply);
+        if self.fifty_move_rule_last_event > self.ply || double_step_ahead {
+            errors.push(PositionError::InconsistentCounters);
+        }
+    }
+
+    // The pawn of the player who is not to move must just have
+    // double-stepped over the skipped field.
+    fn validate_en_passant(&self, errors: &mut Vec<PositionError>) {
+        let skipped = match self.en_passant_field() {
+            Some(skipped) => skipped,
+            None => return,
+        };
+        let player = self.turn().opponent();
+        let target = self.board.en_passant_target();
+        let (skipped_rank, from_rank, target_rank) = match player {
+            Player::White => (2, 1, 3),
+            Player::Black => (5, 6, 4),
+        };
+        let on_file = |rank: usize| rank * 8 + skipped.file();
+        let valid = skipped.rank() == skipped_rank
+            && target == on_file(target_rank)
+            && self.board.field(target) == Some((PieceType::Pawn, player))
+            && self.board.field(skipped.index()).is_none()
+            && self.board.field(on_file(from_rank)).is_none();
+        if !valid {
+            errors.push(PositionError::InvalidEnPassant(skipped.index()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::FenError;
+    use crate::variant::VariantKind;
+
+    fn errors(fen: &str) -> Vec<PositionError> {
+        match GameState::from_fen(fen) {
+            Err(FenError::InvalidPosition(errors)) => errors,
+            result => panic!("{} is not an invalid position: {:?}", fen, result),
+        }
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        assert_eq!(
+            errors("8/8/8/8/8/8/8/K6P w - - 0 1"),
+            vec![
+                PositionError::WrongNumberOfKings { player: Player::Black, kings: 0 },
+                PositionError::PawnOnBackRank(7),
+            ]
+        );
+        assert_eq!(
+            errors("k7/8/8/8/8/8/8/KK2r3 b - - 0 1"),
+            vec![PositionError::WrongNumberOfKings { player: Player::White, kings: 2 }]
+        );
+        assert_eq!(errors("k7/8/8/8/8/8/8/K6r b - - 0 1"), vec![PositionError::OpponentInCheck]);
+        assert_eq!(
+            errors("k7/8/8/8/8/8/PPPPPPPP/QQQQKQQQ w - - 0 1"),
+            vec![PositionError::TooManyPromotedPieces(Player::White)]
+        );
+    }
+
+    #[test]
+    fn en_passant_and_castling() {
+        let mut game_state = GameState::from_fen("4k3/8/8/8/4P3/8/8/R3K2R b KQ e3 0 1").unwrap();
+        assert_eq!(game_state.validate(), Ok(()));
+        let pos = |name: &str| pos_from_algebraic(name).unwrap();
+        game_state.board.set_field(pos("e4"), None);
+        game_state.board.set_field(pos("a1"), None);
+        game_state.board.set_field(pos("a2"), Some((PieceType::InitRook, Player::White)));
+        assert_eq!(
+            game_state.validate(),
+            Err(vec![
+                PositionError::InvalidCastlingRight(pos("a2")),
+                PositionError::InvalidEnPassant(pos("e3")),
+            ])
+        );
+    }
+
+    #[test]
+    fn counters_and_promoted_pieces() {
+        let mut game_state = GameState::new(VariantKind::Standard);
+        game_state.fifty_move_rule_last_event = 10;
+        assert_eq!(game_state.validate(), Err(vec![PositionError::InconsistentCounters]));
+
+        let fen = "4k3/8/8/8/8/8/8/Q3K3 w - - 0 1";
+        let mut game_state = GameState::from_fen_with_variant(fen, VariantKind::Crazyhouse).unwrap();
+        game_state.promoted = bit(pos_from_algebraic("a1").unwrap());
+        assert_eq!(game_state.validate(), Ok(()));
+        game_state.promoted |= bit(pos_from_algebraic("e1").unwrap()) | bit(pos_from_algebraic("a2").unwrap());
+        assert_eq!(
+            game_state.validate(),
+            Err(vec![
+                PositionError::InvalidPromotedPiece(pos_from_algebraic("e1").unwrap()),
+                PositionError::InvalidPromotedPiece(pos_from_algebraic("a2").unwrap()),
+            ])
+        );
+    }
+
+    #[test]
+    fn variants() {
+        let fen = "8/8/8/8/8/8/8/RN6 w - - 0 1";
+        assert!(GameState::from_fen(fen).is_err());
+        assert!(GameState::from_fen_with_variant(fen, VariantKind::Antichess).is_ok());
+    }
+}
@@ -0,0 +1,251 @@
+// Chess variants. A Variant overrides parts of the standard rules: the start
+// position, which moves are legal and when and how the game ends. Every
+// GameState knows which variant is played (VariantKind) and asks it for the
+// rules, so the search and the front ends work for every variant listed in
+// VariantKind without knowing about its rules.
+
+use serde::{Deserialize, Serialize};
+
+use crate::antichess::Antichess;
+use crate::atomic::Atomic;
+use crate::bitboard::Bitboard;
+use crate::chess960::Chess960;
+use crate::crazyhouse::Crazyhouse;
+use crate::game::*;
+
+pub trait Variant: Sync {
+    fn name(&self) -> &'static str;
+
+    fn start_position(&self) -> BoardState {
+        BoardState::new()
+    }
+
+    // Changes the board like the move does, e.g. explosions in Atomic chess.
+    fn apply_move(&self, board: &mut BoardState, mv: Move, ply: usize) -> UndoInfo {
+        board.apply_move(mv, ply)
+    }
+
+    fn in_check(&self, board: &BoardState, player: Player) -> bool {
+        board.king_in_check(player)
+    }
+
+    // Whether a pseudo legal move of the player to move may be played.
+    fn is_legal(&self, game_state: &GameState, mv: Move) -> bool {
+        !game_state.king_in_check_after(mv)
+    }
+
+    // The pieces a pawn may promote to.
+    fn promotion_pieces(&self) -> &'static [PieceType] {
+        &PROMOTION_PIECES
+    }
+
+    // Whether each player has exactly one king which must not be captured.
+    fn royal_king(&self) -> bool {
+        true
+    }
+
+    // Whether GameState::checks has to be maintained.
+    fn counts_checks(&self) -> bool {
+        false
+    }
+
+    // Whether captured pieces go to GameState::pockets and can be dropped.
+    fn uses_pockets(&self) -> bool {
+        false
+    }
+
+    // Whether only captures may be played if there is a legal capture.
+    fn compulsory_captures(&self) -> bool {
+        false
+    }
+
+    // Game ends which do not depend on the legal moves, i.e. extra win
+    // conditions and draws. They are checked before the moves are generated.
+    fn game_end(&self, game_state: &GameState) -> Option<FinishedState> {
+        automatic_draw(game_state).or_else(|| {
+            if game_state.board.insufficient_material() {
+                Some(FinishedState::InsufficientMaterial)
+            } else {
+                None
+            }
+        })
+    }
+
+    // The outcome if the player to move can not move at all.
+    fn no_legal_moves(&self, game_state: &GameState) -> FinishedState {
+        if game_state.in_check() {
+            FinishedState::Checkmate
+        } else {
+            FinishedState::Stalemate
+        }
+    }
+
+    // Whether player could still win by any series of legal moves, e.g. when
+    // the opponent runs out of time. A lone king can not checkmate and
+    // neither can a lone minor piece against a bare king.
+    fn can_win(&self, game_state: &GameState, player: Player) -> bool {
+        let board = &game_state.board;
+        if !game_state.pockets[player as usize].is_empty() {
+            return true;
+        }
+        if lone_king(board, player) {
+            return false;
+        }
+        !board.insufficient_material() || !game_state.pockets[player.opponent() as usize].is_empty()
+    }
+
+    // 1 if having more material is good, -1 if the goal is to lose it.
+    fn material_sign(&self) -> i32 {
+        1
+    }
+
+    // None for draws and ongoing games.
+    fn winner(&self, game_state: &GameState, finished: FinishedState) -> Option<Player> {
+        match finished {
+            FinishedState::Checkmate
+            | FinishedState::KingOfTheHill
+            | FinishedState::ThreeChecks
+            | FinishedState::KingExploded => Some(game_state.turn().opponent()),
+            FinishedState::OutOfMoves => Some(game_state.turn()),
+            _ => None,
+        }
+    }
+}
+
+// The draws which apply to all variants.
+pub fn automatic_draw(game_state: &GameState) -> Option<FinishedState> {
+    if game_state.fifty_move_rule_draw() {
+        Some(FinishedState::FiftyMoveDraw)
+    } else if game_state.fivefold_repetition() {
+        Some(FinishedState::FivefoldRepetition)
+    } else {
+        None
+    }
+}
+
+pub fn lone_king(board: &BoardState, player: Player) -> bool {
+    board.player_pieces(player) == board.pieces(PieceType::King, player)
+}
+
+// Two lone kings can not win in any variant.
+pub fn bare_kings(game_state: &GameState) -> Option<FinishedState> {
+    if game_state.board.occupied().count_ones() == 2 {
+        Some(FinishedState::InsufficientMaterial)
+    } else {
+        None
+    }
+}
+
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "Standard"
+    }
+}
+
+pub struct KingOfTheHill;
+
+// d4, e4, d5 and e5
+const HILL: Bitboard = 0x0000_0018_1800_0000;
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "King of the Hill"
+    }
+
+    fn game_end(&self, game_state: &GameState) -> Option<FinishedState> {
+        let last_player = game_state.turn().opponent();
+        if game_state.board.pieces(PieceType::King, last_player) & HILL != 0 {
+            return Some(FinishedState::KingOfTheHill);
+        }
+        // A lone king can still walk to the center.
+        automatic_draw(game_state)
+    }
+
+    fn can_win(&self, _game_state: &GameState, _player: Player) -> bool {
+        true
+    }
+}
+
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "Three-check"
+    }
+
+    fn counts_checks(&self) -> bool {
+        true
+    }
+
+    fn game_end(&self, game_state: &GameState) -> Option<FinishedState> {
+        let last_player = game_state.turn().opponent();
+        if game_state.checks[last_player as usize] >= 3 {
+            return Some(FinishedState::ThreeChecks);
+        }
+        // Any piece can give checks.
+        automatic_draw(game_state).or_else(|| bare_kings(game_state))
+    }
+
+    fn can_win(&self, game_state: &GameState, player: Player) -> bool {
+        !lone_king(&game_state.board, player)
+    }
+}
+
+// All variants which can be played. This is what is stored in a GameState
+// (and sent to the front ends) to identify the rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum VariantKind {
+    #[default]
+    Standard,
+    Chess960,
+    KingOfTheHill,
+    ThreeCheck,
+    Atomic,
+    Crazyhouse,
+    Antichess,
+}
+
+impl VariantKind {
+    pub const ALL: [VariantKind; 7] = [
+        VariantKind::Standard,
+        VariantKind::Chess960,
+        VariantKind::KingOfTheHill,
+        VariantKind::ThreeCheck,
+        VariantKind::Atomic,
+        VariantKind::Crazyhouse,
+        VariantKind::Antichess,
+    ];
+
+    pub fn rules(self) -> &'static dyn Variant {
+        match self {
+            VariantKind::Standard => &Standard,
+            VariantKind::Chess960 => &Chess960,
+            VariantKind::KingOfTheHill => &KingOfTheHill,
+            VariantKind::ThreeCheck => &ThreeCheck,
+            VariantKind::Atomic => &Atomic,
+            VariantKind::Crazyhouse => &Crazyhouse,
+            VariantKind::Antichess => &Antichess,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.rules().name()
+    }
+
+    // Case, spaces and dashes do not matter, e.g. "three-check" is found for
+    // "Three-check".
+    pub fn from_name(name: &str) -> Option<VariantKind> {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+        VariantKind::ALL
+            .iter()
+            .copied()
+            .find(|variant| normalize(variant.name()) == normalize(name))
+    }
+}
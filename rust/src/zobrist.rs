@@ -0,0 +1,184 @@
+// Zobrist hashing: every feature of a position (a piece on a field, a
+// castling right, an en passant file, the player to move) gets a random
+// 64-bit key and the hash of a position is the XOR of the keys of all its
+// features. Moving a piece then only needs to XOR out the old and XOR in the
+// new keys, see BoardState::set_field().
+//
+// The keys are generated at compile time with a fixed seed so that hashes
+// are the same for every build, e.g. for stored opening books.
+
+use crate::bitboard::{self, PAWN_ATTACKS};
+use crate::crazyhouse::DROP_PIECES;
+use crate::game::*;
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+// 2 players * 6 kinds of pieces * 64 fields, 2 players * 8 rook files for
+// castling rights, 8 en passant files, the player to move and 2 players * 3
+// numbers of checks given (Three-check) and 2 players * 5 kinds of pieces *
+// 16 numbers of pieces in the pocket and 64 fields of promoted pieces
+// (Crazyhouse)
+const NUM_KEYS: usize = 2 * 6 * 64 + 2 * 8 + 8 + 1 + 2 * 3 + 2 * 5 * 16 + 64;
+const PIECE_KEYS_OFFSET: usize = 0;
+const CASTLING_KEYS_OFFSET: usize = 2 * 6 * 64;
+const EN_PASSANT_KEYS_OFFSET: usize = CASTLING_KEYS_OFFSET + 2 * 8;
+const BLACK_TO_MOVE_KEY_OFFSET: usize = EN_PASSANT_KEYS_OFFSET + 8;
+const CHECKS_KEYS_OFFSET: usize = BLACK_TO_MOVE_KEY_OFFSET + 1;
+const POCKET_KEYS_OFFSET: usize = CHECKS_KEYS_OFFSET + 2 * 3;
+const PROMOTED_KEYS_OFFSET: usize = POCKET_KEYS_OFFSET + 2 * 5 * 16;
+
+const fn generate_keys() -> [u64; NUM_KEYS] {
+    let mut keys = [0; NUM_KEYS];
+    let mut state = 0x00C0_FFEE_C4A1_C4A1;
+    let mut i = 0;
+    while i < NUM_KEYS {
+        let (new_state, key) = splitmix64(state);
+        state = new_state;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+}
+
+static KEYS: [u64; NUM_KEYS] = generate_keys();
+
+// The Init* variants are the same kind of piece. Whether they may still
+// castle is covered by the castling keys instead.
+fn kind_index(piece: PieceType) -> usize {
+    match piece {
+        PieceType::InitKing | PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::InitRook | PieceType::Rook => 2,
+        PieceType::Bishop => 3,
+        PieceType::Knight => 4,
+        PieceType::InitPawn | PieceType::Pawn => 5,
+    }
+}
+
+pub fn piece_key(piece: PieceType, player: Player, pos: usize) -> u64 {
+    KEYS[PIECE_KEYS_OFFSET + (player as usize * 6 + kind_index(piece)) * 64 + pos]
+}
+
+pub fn castling_key(player: Player, rook_file: usize) -> u64 {
+    KEYS[CASTLING_KEYS_OFFSET + player as usize * 8 + rook_file]
+}
+
+pub fn en_passant_key(file: usize) -> u64 {
+    KEYS[EN_PASSANT_KEYS_OFFSET + file]
+}
+
+pub fn black_to_move_key() -> u64 {
+    KEYS[BLACK_TO_MOVE_KEY_OFFSET]
+}
+
+// No key for zero checks, more than three count as three.
+pub fn checks_key(player: Player, checks: usize) -> u64 {
+    match checks {
+        0 => 0,
+        checks => KEYS[CHECKS_KEYS_OFFSET + player as usize * 3 + checks.min(3) - 1],
+    }
+}
+
+// No key for an empty pocket. There are only 16 pawns, so no player can
+// ever have more pieces of a kind in the pocket.
+pub fn pocket_key(player: Player, piece: PieceType, count: usize) -> u64 {
+    match count {
+        0 => 0,
+        count => {
+            let kind = kind_index(piece) - 1;
+            KEYS[POCKET_KEYS_OFFSET + (player as usize * 5 + kind) * 16 + count.min(16) - 1]
+        }
+    }
+}
+
+// A promoted piece goes into the pocket as a pawn when it is captured, so
+// it is not the same as the piece it was promoted to.
+pub fn promoted_key(pos: usize) -> u64 {
+    KEYS[PROMOTED_KEYS_OFFSET + pos]
+}
+
+// Position keys do not fit into JavaScript numbers and JSON.parse() would
+// round them, so they are serialized as decimal strings, e.g. with
+// #[serde(with = "zobrist::serde_keys")].
+pub mod serde_keys {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(keys: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+        keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|key| key.parse().map_err(de::Error::custom))
+            .collect()
+    }
+}
+
+// A player may castle with every unmoved rook as long as the king has not
+// moved either. The rights are identified by the file of the rook.
+pub fn castling_rights_hash(board: &BoardState) -> u64 {
+    let mut hash = 0;
+    for &player in [Player::Black, Player::White].iter() {
+        if board.pieces_of_type(PieceType::InitKing, player) == 0 {
+            continue;
+        }
+        for rook_pos in bitboard::fields(board.pieces_of_type(PieceType::InitRook, player)) {
+            hash ^= castling_key(player, rook_pos % 8);
+        }
+    }
+    hash
+}
+
+impl GameState {
+    // The position key used for repetitions, see position_key(), computed
+    // from scratch instead of incrementally.
+    pub fn position_key_from_scratch(&self) -> u64 {
+        let mut hash = 0;
+        for (pos, field) in self.board.fields().iter().enumerate() {
+            if let Some((piece, player)) = *field {
+                hash ^= piece_key(piece, player, pos);
+            }
+        }
+        hash ^ self.non_placement_hash()
+    }
+
+    // Everything except for the piece placement which is tracked
+    // incrementally by the BoardState.
+    pub(crate) fn non_placement_hash(&self) -> u64 {
+        let mut hash = castling_rights_hash(&self.board);
+        // After a double step, the position only differs from the same one
+        // without it if a pawn can actually capture en passant.
+        if let Some(skipped) = self.en_passant_field() {
+            let player = self.turn();
+            let capturers = PAWN_ATTACKS[player.opponent() as usize][skipped.index()]
+                & self.board.pieces(PieceType::Pawn, player);
+            if capturers != 0 {
+                hash ^= en_passant_key(skipped.file());
+            }
+        }
+        if self.turn() == Player::Black {
+            hash ^= black_to_move_key();
+        }
+        for &player in [Player::Black, Player::White].iter() {
+            hash ^= checks_key(player, self.checks[player as usize]);
+            for &piece in DROP_PIECES.iter() {
+                hash ^= pocket_key(player, piece, self.pockets[player as usize].count(piece));
+            }
+        }
+        for pos in bitboard::fields(self.promoted) {
+            hash ^= promoted_key(pos);
+        }
+        hash
+    }
+
+    pub fn verify_position_key(&self) -> bool {
+        self.position_key() == self.position_key_from_scratch()
+    }
+}